@@ -5,9 +5,13 @@ A server for RealtimeSTT made to be used with whisper-overlay.
 """
 
 import argparse
+import collections
+import http.server
 import json
 import logging
+import os
 import queue
+import select
 import socket
 import struct
 import time
@@ -43,12 +47,109 @@ class Client:
         self.thread = threading.current_thread()
         self.mode = None
         self.is_true_client = False
-        self.waiting = False
+        # The time at which the client started waiting for the model lock
+        self.waiting_since = None
+        # The name reported by the client, shown as the lock holder
+        self.name = tag
         self.queue = queue.Queue()
 
 clients = {}
 active_client = None
-model_lock = threading.Lock()
+# The words of the last realtime transcription, used to suggest alternatives for the final result
+realtime_words = []
+# The clients waiting for the model lock, in the order in which they acquire it.
+# Only the client at the front of the queue may take the lock.
+model_condition = threading.Condition()
+model_queue = collections.deque()
+model_locked = False
+# Status clients receive the status at least this often (in seconds), so they can detect dead connections
+heartbeat_interval = 10
+start_time = time.monotonic()
+# The most recent latencies in seconds. Realtime latency is the interval between two
+# realtime updates, final latency the time from the end of a recording to its result.
+realtime_latencies = collections.deque(maxlen=20)
+final_latencies = collections.deque(maxlen=20)
+# The durations for which the most recent stream clients held the model lock
+session_durations = collections.deque(maxlen=20)
+lock_acquired_at = None
+last_realtime_update = None
+recording_stopped_at = None
+# The model options of the current session, such as the initial prompt. RealtimeSTT only passes
+# its options to the models at startup, so these are injected into each call of transcribe.
+session_options = {}
+session_options_supported = False
+# The languages detected by the models in their last transcription
+detected_language = None
+realtime_detected_language = None
+# The transcription worker of RealtimeSTT, if it was replaced in this process
+original_transcription_worker = None
+
+def with_session_options(transcribe, options, detected):
+    """Wraps the transcribe function of a whisper model to apply the given options
+    and to report the detected language."""
+    def wrapped(*args, **kwargs):
+        segments, info = transcribe(*args, **{**kwargs, **options()})
+        detected(info.language)
+        return segments, info
+    return wrapped
+
+class SessionPipe:
+    """Wraps the pipe to the final transcription subprocess of RealtimeSTT, so each
+    request carries the options of the current session and each reply the detected language."""
+    def __init__(self, conn):
+        self.conn = conn
+
+    def send(self, obj):
+        self.conn.send((obj, dict(session_options)))
+
+    def recv(self):
+        global detected_language
+        obj = self.conn.recv()
+        if isinstance(obj, dict) and "session_reply" in obj:
+            detected_language = obj["language"]
+            obj = obj["session_reply"]
+        return obj
+
+    def __getattr__(self, name):
+        return getattr(self.conn, name)
+
+class WorkerPipe:
+    """The counterpart of SessionPipe in the transcription subprocess."""
+    def __init__(self, conn):
+        self.conn = conn
+        self.options = {}
+        self.language = None
+        self.wrapped = False
+
+    def recv(self):
+        obj = self.conn.recv()
+        # Requests are only wrapped if the parent process supports session options
+        if isinstance(obj, tuple) and len(obj) == 2 and isinstance(obj[1], dict):
+            obj, self.options = obj
+            self.wrapped = True
+        return obj
+
+    def send(self, obj):
+        if self.wrapped:
+            obj = dict(session_reply=obj, language=self.language)
+        self.conn.send(obj)
+
+    def detected(self, language):
+        self.language = language
+
+    def __getattr__(self, name):
+        return getattr(self.conn, name)
+
+def transcription_worker(conn, *args, **kwargs):
+    """Runs the final transcription worker of RealtimeSTT in its subprocess,
+    applying the session options received with each request to the model."""
+    import faster_whisper
+    from RealtimeSTT import AudioToTextRecorder
+    pipe = WorkerPipe(conn)
+    faster_whisper.WhisperModel.transcribe = with_session_options(faster_whisper.WhisperModel.transcribe, lambda: pipe.options, pipe.detected)
+    # Subprocesses which are spawned instead of forked still know the original worker
+    worker = original_transcription_worker or AudioToTextRecorder._transcription_worker
+    worker(pipe, *args, **kwargs)
 
 def publish(obj, client=None):
     msg = json.dumps(obj)
@@ -62,9 +163,278 @@ def publish(obj, client=None):
 def refresh_status(client=None):
     publish(dict(refresh_status=True), client=client)
 
+def average_ms(latencies):
+    if len(latencies) == 0:
+        return None
+    return round(1000 * sum(latencies) / len(latencies))
+
+def server_status():
+    """Returns the status which is sent to status clients."""
+    stream_clients = [x for x in list(clients.values()) if x.is_true_client]
+    with model_condition:
+        waiting = list(model_queue)
+    return {
+        "clients": len(stream_clients),
+        "waiting": len(waiting),
+        "models": {"final": args.model, "realtime": args.model_realtime},
+        "device": args.device,
+        "uptime": round(time.monotonic() - start_time),
+        "lock_holder": active_client.name if active_client is not None else None,
+        # The names of the waiting clients, in the order in which they will acquire the lock
+        "queue": [x.name for x in waiting],
+        "latency": {
+            "realtime_ms": average_ms(realtime_latencies),
+            "final_ms": average_ms(final_latencies),
+        },
+        "heartbeat": heartbeat_interval,
+    }
+
+def configure_session(init):
+    """Applies the per-session model settings requested by a stream client,
+    falling back to the server defaults for anything that wasn't requested."""
+    global detected_language
+    global realtime_detected_language
+    language = init.get("language") or args.language
+    # Whisper doesn't know "auto", an empty language lets it detect the language
+    if language == "auto":
+        language = ""
+    initial_prompt = init.get("initial_prompt") or args.initial_prompt or None
+    hotwords = init.get("hotwords") or []
+    task = init.get("task") or "transcribe"
+    # RealtimeSTT has no dedicated hotword support, but listing the words
+    # in the prompt is usually enough for whisper to pick up their spelling.
+    if len(hotwords) > 0:
+        initial_prompt = " ".join(filter(None, [initial_prompt, ", ".join(hotwords) + "."]))
+
+    if initial_prompt is not None and not session_options_supported:
+        logger.warning("ignoring the initial prompt, this version of RealtimeSTT does not support per-session options")
+
+    recorder.language = language
+    detected_language = None
+    realtime_detected_language = None
+    session_options.clear()
+    session_options["initial_prompt"] = initial_prompt
+    session_options["task"] = task
+
+def unsupported_request(init):
+    """Returns the reason why the session requested by a stream client
+    can't be served, or None if it can."""
+    if init.get("task") == "translate" and not session_options_supported:
+        return "translation is not supported by this server"
+    return None
+
+def result_language(realtime=False):
+    """Returns the language of the current session, or the language detected
+    by the final or realtime model if the session uses auto-detection."""
+    if session_options.get("task") == "translate":
+        return "en"
+    return recorder.language or (realtime_detected_language if realtime else detected_language) or None
+
+def word_alternatives(word):
+    """Returns the words of the last realtime transcription which overlap
+    the given word in time, but were transcribed differently."""
+    alternatives = []
+    for x in realtime_words:
+        overlaps = x.start < word.end and x.end > word.start
+        differs = x.word.strip().lower() != word.word.strip().lower()
+        if overlaps and differs and x.word not in alternatives:
+            alternatives.append(x.word)
+    return alternatives
+
+def result_segment(segment):
+    """Converts a final segment for sending, adding alternatives to each word.
+    Words are sent as [start, end, word, probability, alternatives]."""
+    segment = segment._asdict()
+    segment["words"] = [[*w, word_alternatives(w)] for w in segment.get("words") or []]
+    return segment
+
+def queue_position(client):
+    """Returns the 1-based position of a waiting client in the queue
+    and the estimated wait in seconds, if it can be estimated."""
+    with model_condition:
+        waiting = list(model_queue)
+    position = waiting.index(client) + 1 if client in waiting else 1
+    if len(session_durations) == 0:
+        return position, None
+    average = sum(session_durations) / len(session_durations)
+    held = time.monotonic() - lock_acquired_at if lock_acquired_at is not None else 0
+    return position, round(max(average - held, 0) + (position - 1) * average)
+
+def client_disconnected(conn):
+    """Checks whether a client closed its connection without reading from it."""
+    readable, _, _ = select.select([conn], [], [], 0)
+    return len(readable) > 0 and conn.recv(1, socket.MSG_PEEK) == b""
+
+def acquire_model_lock(client):
+    """Waits in the queue for the model lock while sending queue updates to the client.
+    Returns False if the client gave up waiting."""
+    global model_locked
+    with model_condition:
+        model_queue.append(client)
+    refresh_status()
+
+    acquired = False
+    last_update = None
+    try:
+        while True:
+            with model_condition:
+                acquired = model_condition.wait_for(lambda: not model_locked and model_queue[0] is client, timeout=1)
+                if acquired:
+                    model_queue.popleft()
+                    model_locked = True
+                    return True
+
+            if client_disconnected(client.conn):
+                return False
+            update = queue_position(client)
+            if update != last_update:
+                position, eta = update
+                send_message(client.conn, dict(status="queued", position=position, eta=eta))
+                last_update = update
+    finally:
+        if not acquired:
+            with model_condition:
+                model_queue.remove(client)
+                # The next client may be at the front of the queue now
+                model_condition.notify_all()
+
+def release_model_lock():
+    global model_locked
+    with model_condition:
+        model_locked = False
+        model_condition.notify_all()
+
+class Histogram:
+    """A histogram of durations in seconds, rendered in the OpenMetrics text format."""
+    buckets = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]
+
+    def __init__(self):
+        self.counts = [0] * len(self.buckets)
+        self.sum = 0.0
+        self.count = 0
+
+    def observe(self, value):
+        with metrics_lock:
+            for i, bound in enumerate(self.buckets):
+                if value <= bound:
+                    self.counts[i] += 1
+                    break
+            self.sum += value
+            self.count += 1
+
+    def render(self, name, labels=""):
+        lines = []
+        cumulative = 0
+        for bound, count in zip(self.buckets, self.counts):
+            cumulative += count
+            lines.append(f'{name}_bucket{{{labels}le="{bound}"}} {cumulative}')
+        lines.append(f'{name}_bucket{{{labels}le="+Inf"}} {self.count}')
+        suffix = f"{{{labels.rstrip(',')}}}" if labels else ""
+        lines.append(f"{name}_sum{suffix} {self.sum}")
+        lines.append(f"{name}_count{suffix} {self.count}")
+        return lines
+
+metrics_lock = threading.Lock()
+metrics = {
+    "sessions": 0,
+    "audio_seconds": 0.0,
+    "words": 0,
+}
+error_counts = collections.Counter()
+lock_wait_histogram = Histogram()
+latency_histograms = {"realtime": Histogram(), "final": Histogram()}
+
+def count_metric(name, value=1):
+    with metrics_lock:
+        metrics[name] += value
+
+def count_error(kind):
+    with metrics_lock:
+        error_counts[kind] += 1
+
+def render_metrics():
+    """Renders all metrics in the OpenMetrics text format."""
+    stream_clients = [x for x in list(clients.values()) if x.is_true_client]
+    lines = []
+    def family(name, kind, help):
+        lines.append(f"# TYPE whisper_server_{name} {kind}")
+        lines.append(f"# HELP whisper_server_{name} {help}")
+
+    with metrics_lock:
+        family("sessions", "counter", "Sessions in which a client acquired the model lock")
+        lines.append(f"whisper_server_sessions_total {metrics['sessions']}")
+        family("audio_seconds", "counter", "Seconds of audio received from clients")
+        lines.append(f"whisper_server_audio_seconds_total {metrics['audio_seconds']}")
+        family("words", "counter", "Words in final transcriptions")
+        lines.append(f"whisper_server_words_total {metrics['words']}")
+        family("errors", "counter", "Errors by kind")
+        for kind, count in sorted(error_counts.items()):
+            lines.append(f'whisper_server_errors_total{{kind="{kind}"}} {count}')
+        family("clients", "gauge", "Connected stream clients")
+        lines.append(f"whisper_server_clients {len(stream_clients)}")
+        family("waiting_clients", "gauge", "Stream clients waiting for the model lock")
+        lines.append(f"whisper_server_waiting_clients {len(model_queue)}")
+        family("lock_wait_seconds", "histogram", "Time clients waited for the model lock")
+        lines.extend(lock_wait_histogram.render("whisper_server_lock_wait_seconds"))
+        family("transcription_latency_seconds", "histogram", "Interval between realtime updates, and time from the end of a recording to its final result")
+        for kind, histogram in latency_histograms.items():
+            lines.extend(histogram.render("whisper_server_transcription_latency_seconds", f'kind="{kind}",'))
+    lines.append("# EOF")
+    return "\n".join(lines) + "\n"
+
+class MetricsHandler(http.server.BaseHTTPRequestHandler):
+    def do_GET(self):
+        if self.path != "/metrics":
+            self.send_error(404)
+            return
+        body = render_metrics().encode("utf-8")
+        self.send_response(200)
+        self.send_header("Content-Type", "application/openmetrics-text; version=1.0.0; charset=utf-8")
+        self.send_header("Content-Length", str(len(body)))
+        self.end_headers()
+        self.wfile.write(body)
+
+    def log_message(self, format, *args):
+        logger.debug(f"metrics: {format % args}")
+
+def serve_metrics(host, port):
+    server = http.server.ThreadingHTTPServer((host, port), MetricsHandler)
+    logger.info(f'Serving metrics on http://{host}:{port}/metrics')
+    thread = threading.Thread(target=server.serve_forever)
+    thread.daemon = True
+    thread.start()
+
+def sd_notify(state):
+    # Only set if started by systemd with Type=notify
+    address = os.environ.get("NOTIFY_SOCKET")
+    if not address:
+        return
+    if address.startswith("@"):
+        address = "\0" + address[1:]
+    try:
+        with socket.socket(socket.AF_UNIX, socket.SOCK_DGRAM) as s:
+            s.connect(address)
+            s.sendall(state.encode("utf-8"))
+    except OSError as e:
+        logger.warning(f"could not notify systemd: {e}")
+
+def listen_socket(host, port):
+    # Use the socket passed by systemd socket activation, which always starts at fd 3
+    if os.environ.get("LISTEN_PID") == str(os.getpid()) and int(os.environ.get("LISTEN_FDS", "0")) >= 1:
+        logger.info('Starting server on socket passed by systemd')
+        return socket.socket(fileno=3)
+
+    logger.info(f'Starting server on {host}:{port}')
+    s = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+    s.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+    s.bind((host, port))
+    s.listen()
+    return s
+
 def handle_client(conn, addr):
     global recorder
     global active_client
+    global lock_acquired_at
     tag = f"{addr[0]}:{addr[1]}"
     client = Client(tag, conn)
     clients[addr] = client
@@ -75,33 +445,46 @@ def handle_client(conn, addr):
         logger.info(f'{tag} Client requested mode {init["mode"]}')
         client.mode = init["mode"]
         client.is_true_client = init["mode"] == "stream"
+        client.name = init.get("name") or tag
 
         if init["mode"] == "status":
             refresh_status(client) # refresh once after startup
 
             while True:
-                message = json.loads(client.queue.get())
+                try:
+                    message = json.loads(client.queue.get(timeout=heartbeat_interval))
+                except queue.Empty:
+                    # Repeat the status as a heartbeat
+                    send_message(conn, server_status())
+                    continue
 
                 if "refresh_status" in message and message["refresh_status"] == True:
-                    n_clients = len(list(filter(lambda x: x.is_true_client, clients.values())))
-                    n_waiting = len(list(filter(lambda x: x.is_true_client and x.waiting, clients.values())))
-                    status = {
-                        "clients": n_clients,
-                        "waiting": n_waiting,
-                    }
-                    send_message(conn, status)
+                    send_message(conn, server_status())
                     client.queue.task_done()
         else:
+            reason = unsupported_request(init)
+            if reason is not None:
+                logger.info(f'{tag} Refusing session: {reason}')
+                send_message(conn, dict(status=reason))
+                return
+
             logger.info(f'{tag} Acquiring lock')
-            client.waiting = True
-            refresh_status()
+            client.waiting_since = time.monotonic()
             send_message(conn, dict(status="waiting for lock"))
 
-            with model_lock:
+            if not acquire_model_lock(client):
+                logger.info(f'{tag} Client stopped waiting for the lock')
+                return
+
+            try:
                 active_client = client
-                client.waiting = False
+                lock_acquired_at = time.monotonic()
+                lock_wait_histogram.observe(lock_acquired_at - client.waiting_since)
+                count_metric("sessions")
+                client.waiting_since = None
                 refresh_status()
                 send_message(conn, dict(status="lock acquired"))
+                configure_session(init)
                 recorder.start()
 
                 def send_queue():
@@ -126,6 +509,8 @@ def handle_client(conn, addr):
                             break
 
                         if isinstance(msg, bytes):
+                            # 16-bit mono samples at 16 kHz
+                            count_metric("audio_seconds", len(msg) / 32000)
                             recorder.feed_audio(msg)
                             continue
 
@@ -138,16 +523,25 @@ def handle_client(conn, addr):
                             logger.info(f"{tag} flushed")
                             continue
                         else:
+                            count_error("protocol")
                             logger.info(f"{tag} error in recv: invalid message: {msg}")
                             continue
                 except (OSError, ConnectionError):
+                    count_error("connection")
                     logger.info(f"{tag} error in recv: connection closed?")
                 finally:
                     client.queue.put(None)
                     active_client = None
                     recorder.stop()
+                    configure_session({})
                     sender_thread.join()
+            finally:
+                active_client = None
+                session_durations.append(time.monotonic() - lock_acquired_at)
+                lock_acquired_at = None
+                release_model_lock()
     except Exception as e:
+        count_error("client")
         import traceback
         traceback.print_exc()
         logger.error(f'{tag} Error handling client: {e}')
@@ -174,7 +568,11 @@ if __name__ == "__main__":
     parser.add_argument("--model-realtime", type=str, default="base",
         help="Faster model used to generate live transcriptions [default: 'base']")
     parser.add_argument("--language", type=str, default="",
-        help="Set the spoken language. Leave empty to auto-detect. [default: '']")
+        help="Set the spoken language. Leave empty to auto-detect. Clients may override this per session. [default: '']")
+    parser.add_argument("--initial-prompt", type=str, default="",
+        help="Initial prompt passed to the models. Clients may override this per session. [default: '']")
+    parser.add_argument("--metrics-port", type=int, default=0,
+        help="Serve prometheus metrics on this port on the same host, 0 disables metrics [default: 0]")
     parser.add_argument("--debug", action="store_true",
         help="Enable debug log output [default: unset]")
 
@@ -191,12 +589,30 @@ if __name__ == "__main__":
     logger.info("Importing runtime")
     from RealtimeSTT import AudioToTextRecorder
 
+    # Must be replaced before the recorder starts its subprocess
+    original_transcription_worker = AudioToTextRecorder._transcription_worker
+    AudioToTextRecorder._transcription_worker = staticmethod(transcription_worker)
+
     def text_detected(ts):
         text, segments = ts
         global active_client
+        global realtime_words
+        global last_realtime_update
+        now = time.monotonic()
+        if last_realtime_update is not None:
+            realtime_latencies.append(now - last_realtime_update)
+            latency_histograms["realtime"].observe(now - last_realtime_update)
+        last_realtime_update = now
+        realtime_words = [w for x in segments for w in (x.words or [])]
         if active_client is not None:
             segments = [x._asdict() for x in segments]
-            active_client.queue.put(dict(kind="realtime", text=text, segments=segments))
+            active_client.queue.put(dict(kind="realtime", text=text, segments=segments, language=result_language(realtime=True)))
+
+    def recording_stopped():
+        global recording_stopped_at
+        global last_realtime_update
+        recording_stopped_at = time.monotonic()
+        last_realtime_update = None
 
     recorder_ready = threading.Event()
     recorder_config = {
@@ -220,13 +636,27 @@ if __name__ == "__main__":
         'realtime_model_type': args.model_realtime,
 
         'on_realtime_transcription_stabilized': text_detected,
+        'on_recording_stop': recording_stopped,
     }
 
     def recorder_thread():
         global recorder
         global active_client
+        global realtime_words
+        global recording_stopped_at
+        global session_options_supported
         logger.info("Initializing RealtimeSTT...")
         recorder = AudioToTextRecorder(**recorder_config)
+        realtime_model = getattr(recorder, "realtime_model_type", None)
+        if hasattr(recorder, "parent_transcription_pipe") and hasattr(realtime_model, "transcribe"):
+            recorder.parent_transcription_pipe = SessionPipe(recorder.parent_transcription_pipe)
+            def realtime_detected(language):
+                global realtime_detected_language
+                realtime_detected_language = language
+            realtime_model.transcribe = with_session_options(realtime_model.transcribe, lambda: session_options, realtime_detected)
+            session_options_supported = True
+        else:
+            logger.warning("unsupported version of RealtimeSTT, per-session options are ignored")
         logger.info("AudioToTextRecorder ready")
         recorder_ready.set()
         try:
@@ -235,8 +665,15 @@ if __name__ == "__main__":
                 if text == "":
                     continue
                 if active_client is not None:
-                    segments = [x._asdict() for x in segments]
-                    active_client.queue.put(dict(kind="result", text=text, segments=segments))
+                    segments = [result_segment(x) for x in segments]
+                    active_client.queue.put(dict(kind="result", text=text, segments=segments, language=result_language()))
+                realtime_words = []
+                count_metric("words", len(text.split()))
+                if recording_stopped_at is not None:
+                    final_latencies.append(time.monotonic() - recording_stopped_at)
+                    latency_histograms["final"].observe(time.monotonic() - recording_stopped_at)
+                    recording_stopped_at = None
+                    refresh_status()
         except (OSError, EOFError) as e:
             logger.info(f"recorder thread failed: {e}")
             return
@@ -245,12 +682,12 @@ if __name__ == "__main__":
     recorder_thread.start()
     recorder_ready.wait()
 
-    logger.info(f'Starting server on {args.host}:{args.port}')
-    with socket.socket(socket.AF_INET, socket.SOCK_STREAM) as s:
-        s.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
-        s.bind((args.host, args.port))
-        s.listen()
+    if args.metrics_port != 0:
+        serve_metrics(args.host, args.metrics_port)
+
+    with listen_socket(args.host, args.port) as s:
         logger.info(f'Server ready to accept connections')
+        sd_notify("READY=1")
 
         try:
             while True:
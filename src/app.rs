@@ -1,41 +1,133 @@
-use color_eyre::eyre::{bail, Context, Result};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use color_eyre::eyre::{bail, eyre, Context, Result};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use evdev::Key;
 use futures_util::StreamExt;
 use gdk::glib::ExitCode;
 use gdk_wayland::{prelude::*, WaylandSurface};
 use gtk::cairo::{RectangleInt, Region};
 use gtk::gdk::Display;
-use gtk::{glib, Application, ApplicationWindow, Label};
+use gtk::{gio, glib, Application, ApplicationWindow};
 use gtk::{prelude::*, CssProvider};
-use gtk_layer_shell::{Layer, LayerShell};
+use gtk_layer_shell::LayerShell;
 use serde::Deserialize;
 use serde_json::json;
+use std::cell::Cell;
+use std::ffi::{OsStr, OsString};
+use std::io::Write;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant};
+use thiserror::Error;
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
-use tokio::sync::{mpsc, watch};
+use tokio::sync::{mpsc, oneshot, watch};
 use tokio::task::JoinHandle;
 use tokio_util::codec::LengthDelimitedCodec;
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
-use crate::cli::{Command, ConnectionOpts};
+use crate::cli::{requested_language, Cli, Command, ConnectionOpts, SessionOpts, Task};
+use crate::config::load_config;
+use crate::control::{ControlRequest, OverlayState, SessionState};
+use crate::display::{DisplayMode, OverlayWidgets};
+use crate::focus::focused_app;
 use crate::hotkeys::HotkeyEvent;
-use crate::keyboard::spawn_virtual_keyboard;
+use crate::keyboard::{
+    spawn_virtual_keyboard, KeyboardAction, KeyboardSender, TypingBackend, TypingOpts,
+};
+use crate::live::{LiveTyper, LiveTypingOpts, LiveWord};
+use crate::logging::redact;
+use crate::metrics::METRICS;
+use crate::notification::{NotificationOpts, Notifier, Urgency};
+use crate::output::{post_process, OutputSink, ReviewOpts};
+use crate::overlay::{
+    init_placement, place_window, resolve_placement, OutputSelection, OverlayOpts, Placement,
+};
+use crate::profile::{parse_key, resolve_profiles, Profile};
+use crate::rules::AppRule;
 use crate::runtime;
+use crate::sound::{Cue, SoundCues, SoundOpts};
+use crate::theme::{Status, Theme};
 use crate::util::{recv_message, send_audio_data, send_message};
 
-const APP_ID: &str = "org.oddlama.whisper-overlay";
+pub const APP_ID: &str = "org.oddlama.whisper-overlay";
+
+/// The number of transcriptions kept for the history of the control socket
+const HISTORY_SIZE: usize = 50;
+
+/// The minimum interval between two microphone level updates
+const LEVEL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A repeated error is only announced by a sound and a notification
+/// again after this interval
+const ERROR_REPEAT_INTERVAL: Duration = Duration::from_secs(60);
 
 #[derive(Debug)]
 pub enum UiAction {
     ModelResult(serde_json::Value),
     Disconnected(Option<String>),
+    /// The session was not started for the given reason, e.g. because a rule disables it
+    Refused(String),
+    /// An error which doesn't end the session, such as failing to type the text
+    Error(String),
     Connecting,
     Connected,
     Locking,
+    /// The position in the queue for the model lock and the estimated wait in seconds
+    Queued(u32, Option<u64>),
+    /// Recording has stopped and the server is processing the final result
+    Processing,
+    /// Waiting for the model lock with the given id takes longer than expected
+    LockWaitTimeout(u64),
     HideWindow,
-    ShowWindow,
+    ShowWindow(Placement),
+    SessionStarted(Profile),
+    LanguageChanged(Option<String>),
+    AcceptReview,
+    DiscardReview,
+    /// The review timeout with the given id has expired
+    ReviewTimeout(u64),
+    /// Selects the next or previous low confidence word in the text pending review
+    SelectWord(isize),
+    /// Replaces the selected word with its next or previous alternative
+    CycleAlternative(isize),
+    /// Starts or stops dictating the selected word again
+    Redictate(bool),
+    /// The current microphone level, ranging from 0 to 1
+    MicLevel(f64),
+    /// Switches the overlay to the next display mode
+    CycleDisplayMode,
+    /// Switches the overlay to the given display mode
+    SetDisplayMode(DisplayMode),
+    /// Discards the text of the current session
+    Cancel,
+}
+
+#[derive(Debug, Error)]
+pub enum AudioError {
+    #[error("No input device available")]
+    NoInputDevice,
+    #[error("Could not build the audio input stream: {0}")]
+    BuildStream(#[from] cpal::BuildStreamError),
+    #[error("Could not start the audio input stream: {0}")]
+    PlayStream(#[from] cpal::PlayStreamError),
+}
+
+#[derive(Debug, Error)]
+pub enum SessionError {
+    #[error("The user interface has stopped")]
+    UiClosed,
+    #[error("Audio capture is unavailable: {0}")]
+    Audio(AudioError),
+    #[error("The audio thread stopped unexpectedly")]
+    AudioThread,
+}
+
+impl From<mpsc::error::SendError<UiAction>> for SessionError {
+    fn from(_: mpsc::error::SendError<UiAction>) -> Self {
+        SessionError::UiClosed
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -44,14 +136,16 @@ pub enum ConnectionState {
     Disconnected,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Word {
-    #[allow(unused)]
     begin: f32,
     #[allow(unused)]
     end: f32,
     word: String,
     probability: f32,
+    /// Other candidates for this word, if provided by the server
+    #[serde(default)]
+    alternatives: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -65,39 +159,181 @@ pub struct ModelResult {
     #[allow(unused)]
     text: String,
     segments: Vec<Segment>,
+    /// The language used for this result, which is the detected
+    /// language if the session requested auto-detection.
+    #[serde(default)]
+    language: Option<String>,
+}
+
+/// Builds the initial message for a streaming session, which includes
+/// any per-session model settings that the server should honor.
+fn stream_init_message(session_opts: &SessionOpts, client_name: Option<&str>) -> serde_json::Value {
+    let mut init = json!({"mode": "stream"});
+    if let Some(name) = client_name {
+        init["name"] = json!(name);
+    }
+    if let Some(language) = &session_opts.language {
+        init["language"] = json!(language);
+    }
+    if let Some(initial_prompt) = &session_opts.initial_prompt {
+        init["initial_prompt"] = json!(initial_prompt);
+    }
+    if !session_opts.hotwords.is_empty() {
+        init["hotwords"] = json!(session_opts.hotwords);
+    }
+    if let Some(task) = session_opts.task {
+        init["task"] = json!(task.as_str());
+    }
+    init
 }
 
 async fn connect_whisper(
     connection_opts: &ConnectionOpts,
+    session_opts: &SessionOpts,
+    client_name: Option<&str>,
 ) -> Result<(OwnedReadHalf, OwnedWriteHalf)> {
-    println!("Connecting to {}", connection_opts.address);
+    debug!(address = %connection_opts.address, "Connecting");
     let (socket_read, mut socket_write) = TcpStream::connect(&connection_opts.address)
         .await?
         .into_split();
-    println!("Connected to {}", connection_opts.address);
+    info!(address = %connection_opts.address, "Connected");
 
-    send_message(&mut socket_write, json!({"mode": "stream"})).await?;
+    send_message(
+        &mut socket_write,
+        stream_init_message(session_opts, client_name),
+    )
+    .await?;
     Ok((socket_read, socket_write))
 }
 
-async fn set_disconnect_status(ui_sender: &mpsc::Sender<UiAction>, message: &serde_json::Value) {
+async fn set_disconnect_status(
+    ui_sender: &mpsc::Sender<UiAction>,
+    message: &serde_json::Value,
+) -> Result<(), SessionError> {
+    METRICS.error("server");
     let text = if let Some(status) = message.get("status").and_then(|x| x.as_str()) {
         status.to_string()
     } else {
         message.to_string()
     };
-    ui_sender
-        .send(UiAction::Disconnected(Some(text)))
-        .await
-        .unwrap();
+    ui_sender.send(UiAction::Disconnected(Some(text))).await?;
+    Ok(())
+}
+
+/// Keeps the window open for another 4 seconds if no other event takes priority
+async fn hide_window_later(
+    ui_sender: &mpsc::Sender<UiAction>,
+    connection_receiver: &mut watch::Receiver<ConnectionState>,
+) -> Result<(), SessionError> {
+    tokio::select! {
+        _ = tokio::time::sleep(Duration::from_millis(4000)) => {
+            ui_sender.send(UiAction::HideWindow).await?;
+        },
+        _ = connection_receiver.changed() => {
+            connection_receiver.mark_changed();
+            // Early break so the request can be prioritized
+        }
+    };
+    Ok(())
+}
+
+/// Parses a length delimited frame received from the server
+fn parse_frame<B: AsRef<[u8]>>(frame: std::io::Result<B>) -> Result<serde_json::Value> {
+    frame
+        .wrap_err("Failed to read next message")
+        .and_then(|x| {
+            String::from_utf8(x.as_ref().to_vec()).wrap_err("Failed to convert message to utf8")
+        })
+        .and_then(|x| serde_json::from_str(&x).wrap_err("Failed to parse json"))
+}
+
+/// Describes a position in the queue for the model lock, such as "2nd, ~5s"
+fn queue_text(position: u32, eta: Option<u64>) -> String {
+    let suffix = match (position % 10, position % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    match eta {
+        Some(eta) => format!("{position}{suffix}, ~{eta}s"),
+        None => format!("{position}{suffix}"),
+    }
+}
+
+/// Starts capturing mono 16kHz audio from the default input device. Captured
+/// samples are appended to `bytes` while `audio_active` is set.
+fn capture_audio(
+    bytes: Arc<Mutex<Vec<u8>>>,
+    audio_tx: watch::Sender<()>,
+    audio_active: Arc<Mutex<bool>>,
+) -> Result<cpal::Stream, AudioError> {
+    let host = cpal::default_host();
+    let device = host
+        .default_input_device()
+        .ok_or(AudioError::NoInputDevice)?;
+    info!(
+        device = device.name().as_deref().unwrap_or("unknown"),
+        "Using input device"
+    );
+
+    let config = cpal::StreamConfig {
+        channels: 1,
+        sample_rate: cpal::SampleRate(16000),
+        buffer_size: cpal::BufferSize::Default,
+    };
+
+    let err_fn = move |err| {
+        error!("An error occurred on the audio stream: {err}");
+    };
+
+    let stream = device.build_input_stream(
+        &config,
+        move |data: &[i16], _: &_| {
+            if !*audio_active.lock().expect("Could not lock audio stop") {
+                // BUG: https://github.com/RustAudio/cpal/issues/771
+                return;
+            }
+            bytes
+                .lock()
+                .expect("Could not lock mutex to write audio data")
+                .extend_from_slice(bytemuck::cast_slice(data));
+            let _ = audio_tx.send(());
+        },
+        err_fn,
+        None,
+    )?;
+
+    stream.play()?;
+    Ok(stream)
+}
+
+/// Returns the level of the given 16-bit audio samples, scaled
+/// from 0 (-60 dBFS or less) to 1 (0 dBFS).
+fn audio_level(data: &[u8]) -> f64 {
+    let (sum, count) = data
+        .chunks_exact(2)
+        .map(|x| i16::from_ne_bytes([x[0], x[1]]) as f64 / i16::MAX as f64)
+        .fold((0.0, 0), |(sum, count), x| (sum + x * x, count + 1));
+    if count == 0 {
+        return 0.0;
+    }
+
+    let rms = (sum / count as f64).sqrt();
+    ((20.0 * rms.max(1e-6).log10() + 60.0) / 60.0).clamp(0.0, 1.0)
 }
 
 async fn handle_connection(
     mut connection_receiver: watch::Receiver<ConnectionState>,
     ui_sender: mpsc::Sender<UiAction>,
-    connection_opts: ConnectionOpts,
-) {
-    ui_sender.send(UiAction::Disconnected(None)).await.unwrap();
+    profile_receiver: watch::Receiver<Profile>,
+    rules: Vec<AppRule>,
+    output_selection: OutputSelection,
+    display_mode: watch::Receiver<DisplayMode>,
+    client_name: Option<String>,
+) -> Result<(), SessionError> {
+    ui_sender.send(UiAction::Disconnected(None)).await?;
 
     let bytes = Arc::new(Mutex::new(Vec::<u8>::new()));
     let bytes_2 = bytes.clone();
@@ -106,48 +342,33 @@ async fn handle_connection(
     let audio_active = Arc::new(Mutex::new(false));
     let audio_active_2 = audio_active.clone();
 
+    let (audio_ready_tx, audio_ready_rx) = oneshot::channel();
     let audio_thread = std::thread::spawn(move || {
-        let host = cpal::default_host();
-        let device = host
-            .default_input_device()
-            .expect("No input device available");
-
-        println!("Input device: {}", device.name().unwrap());
-
-        let config = cpal::StreamConfig {
-            channels: 1,
-            sample_rate: cpal::SampleRate(16000),
-            buffer_size: cpal::BufferSize::Default,
-        };
-
-        let err_fn = move |err| {
-            eprintln!("an error occurred on the audio stream: {}", err);
+        let stream = match capture_audio(bytes_2, audio_tx, audio_active_2) {
+            Ok(stream) => stream,
+            Err(e) => {
+                let _ = audio_ready_tx.send(Err(e));
+                return;
+            }
         };
-
-        let stream = device
-            .build_input_stream(
-                &config,
-                move |data: &[i16], _: &_| {
-                    if !*audio_active_2.lock().expect("Could not lock audio stop") {
-                        // BUG: https://github.com/RustAudio/cpal/issues/771
-                        return;
-                    }
-                    bytes_2
-                        .lock()
-                        .expect("Could not lock mutex to write audio data")
-                        .extend_from_slice(bytemuck::cast_slice(data));
-                    let _ = audio_tx.send(());
-                },
-                err_fn,
-                None,
-            )
-            .expect("Failed to build audio input stream");
-
-        stream.play().expect("Failed to start audio stream");
+        let _ = audio_ready_tx.send(Ok(()));
+        // The stream is stopped when it is dropped
         let _ = audio_shutdown_rx.recv();
+        drop(stream);
     });
 
-    loop {
+    // Without audio, sessions are refused but the overlay keeps running, so the reason is visible
+    let audio_error = match audio_ready_rx.await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(SessionError::Audio(e)),
+        Err(_) => Some(SessionError::AudioThread),
+    };
+    if let Some(e) = &audio_error {
+        error!("{e}");
+    }
+
+    let mut session_id: u64 = 0;
+    'session: loop {
         {
             if connection_receiver.changed().await.is_err() {
                 break;
@@ -157,24 +378,64 @@ async fn handle_connection(
             let desired_state = *connection_receiver.borrow_and_update();
             match desired_state {
                 ConnectionState::Connected => {
-                    ui_sender.send(UiAction::ShowWindow).await.unwrap();
+                    if let Some(e) = &audio_error {
+                        ui_sender
+                            .send(UiAction::Disconnected(Some(e.to_string())))
+                            .await?;
+                        continue;
+                    }
+
+                    let mode = *display_mode.borrow();
+                    let placement = resolve_placement(&output_selection, mode).await;
+                    ui_sender.send(UiAction::ShowWindow(placement)).await?;
                 }
                 ConnectionState::Disconnected => {
-                    ui_sender.send(UiAction::HideWindow).await.unwrap();
+                    ui_sender.send(UiAction::HideWindow).await?;
                     continue;
                 }
             }
 
-            ui_sender.send(UiAction::Connecting).await.unwrap();
-            let (mut socket_read, mut socket_write) = match connect_whisper(&connection_opts).await
+            let mut profile = profile_receiver.borrow().clone();
+            session_id += 1;
+            let session = info_span!("session", id = session_id, profile = %profile.name);
+            match focused_app().await {
+                Ok(Some(app)) => {
+                    if let Some(rule) = rules.iter().find(|x| x.matches(&app)) {
+                        if rule.disable {
+                            info!(parent: &session, app_id = %app.app_id, "Not starting session, disabled for this application");
+                            ui_sender
+                                .send(UiAction::Refused(format!("disabled for {}", app.app_id)))
+                                .await?;
+                            continue;
+                        }
+
+                        debug!(parent: &session, app_id = %app.app_id, "Applying rule");
+                        rule.apply(&mut profile);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => warn!(parent: &session, "Could not determine focused application: {e}"),
+            }
+
+            ui_sender
+                .send(UiAction::SessionStarted(profile.clone()))
+                .await?;
+            ui_sender.send(UiAction::Connecting).await?;
+            let (mut socket_read, mut socket_write) = match connect_whisper(
+                &profile.connection_opts,
+                &profile.session_opts,
+                client_name.as_deref(),
+            )
+            .instrument(session.clone())
+            .await
             {
                 Ok(s) => s,
                 Err(e) => {
-                    eprintln!("Failed to connect to {}: {}", connection_opts.address, e);
+                    METRICS.error("connect");
+                    error!(parent: &session, address = %profile.connection_opts.address, "Failed to connect: {e}");
                     ui_sender
                         .send(UiAction::Disconnected(Some(e.to_string())))
-                        .await
-                        .unwrap();
+                        .await?;
                     continue;
                 }
             };
@@ -182,54 +443,87 @@ async fn handle_connection(
             match recv_message(&mut socket_read).await {
                 Ok(message) => {
                     if message.get("status") != Some(&json!("waiting for lock")) {
-                        eprintln!("error: received unexpected message: {}", message);
-                        set_disconnect_status(&ui_sender, &message).await;
+                        error!(parent: &session, %message, "Received unexpected message");
+                        set_disconnect_status(&ui_sender, &message).await?;
                         continue;
                     }
                 }
                 Err(e) => {
-                    eprintln!("could not receive message from socket: {}", e);
+                    METRICS.error("connection");
+                    error!(parent: &session, "Could not receive message from socket: {e}");
                     ui_sender
                         .send(UiAction::Disconnected(Some(e.to_string())))
-                        .await
-                        .unwrap();
+                        .await?;
                     continue;
                 }
             }
 
-            ui_sender.send(UiAction::Locking).await.unwrap();
+            ui_sender.send(UiAction::Locking).await?;
+            let locking_since = Instant::now();
 
-            match recv_message(&mut socket_read).await {
-                Ok(message) => {
-                    if message.get("status") != Some(&json!("lock acquired")) {
-                        eprintln!("error: received unexpected message: {}", message);
-                        set_disconnect_status(&ui_sender, &message).await;
-                        continue;
+            let mut read_message_frame = LengthDelimitedCodec::builder()
+                .length_field_offset(0) // default value
+                .length_field_length(4)
+                .length_adjustment(0) // default value
+                .num_skip(4) // skip the first 4 bytes
+                .new_read(socket_read);
+
+            // Wait for the model lock. The server sends queue updates while another client holds it.
+            loop {
+                tokio::select! {
+                    message = read_message_frame.next() => {
+                        let message = match message {
+                            Some(frame) => parse_frame(frame),
+                            None => Err(eyre!("Connection closed by the server")),
+                        };
+                        match message {
+                            Ok(message) if message.get("status") == Some(&json!("lock acquired")) => break,
+                            Ok(message) if message.get("status") == Some(&json!("queued")) => {
+                                let position = message["position"].as_u64().unwrap_or(0) as u32;
+                                let eta = message["eta"].as_u64();
+                                ui_sender.send(UiAction::Queued(position, eta)).await?;
+                            }
+                            Ok(message) => {
+                                error!(parent: &session, %message, "Received unexpected message");
+                                set_disconnect_status(&ui_sender, &message).await?;
+                                hide_window_later(&ui_sender, &mut connection_receiver).await?;
+                                continue 'session;
+                            }
+                            Err(e) => {
+                                METRICS.error("connection");
+                                error!(parent: &session, "Could not receive message from socket: {e:#}");
+                                ui_sender
+                                    .send(UiAction::Disconnected(Some(e.to_string())))
+                                    .await?;
+                                hide_window_later(&ui_sender, &mut connection_receiver).await?;
+                                continue 'session;
+                            }
+                        }
+                    }
+                    _ = connection_receiver.changed() => {
+                        // Nothing was recorded yet, so releasing the hotkey leaves the queue
+                        if *connection_receiver.borrow_and_update() == ConnectionState::Disconnected {
+                            info!(parent: &session, "Stopped waiting for the model lock");
+                            ui_sender.send(UiAction::Disconnected(None)).await?;
+                            hide_window_later(&ui_sender, &mut connection_receiver).await?;
+                            continue 'session;
+                        }
                     }
-                }
-                Err(e) => {
-                    eprintln!("could not receive message from socket: {}", e);
-                    ui_sender
-                        .send(UiAction::Disconnected(Some(e.to_string())))
-                        .await
-                        .unwrap();
-                    continue;
                 }
             }
 
-            ui_sender.send(UiAction::Connected).await.unwrap();
+            ui_sender.send(UiAction::Connected).await?;
+            METRICS.sessions.inc();
+            METRICS.lock_wait.observe(locking_since.elapsed());
 
             let (shutdown_tx, mut shutdown_rx) = watch::channel(());
             // Start audio thread
             *audio_active.lock().expect("Could not lock audio stop") = true;
 
             let mut shutdown_timer: Option<JoinHandle<()>> = None;
-            let mut read_message_frame = LengthDelimitedCodec::builder()
-                .length_field_offset(0) // default value
-                .length_field_length(4)
-                .length_adjustment(0) // default value
-                .num_skip(4) // skip the first 4 bytes
-                .new_read(socket_read);
+            let mut last_level = Instant::now();
+            // The time at which the server was asked for the final result
+            let mut flushed_at: Option<Instant> = None;
 
             loop {
                 tokio::select! {
@@ -238,13 +532,14 @@ async fn handle_connection(
                             continue;
                         };
 
-                        let message: Result<serde_json::Value> = message.wrap_err("Failed to read next message")
-                            .and_then(|x| String::from_utf8(x.to_vec()).wrap_err("Failed to convert message to utf8"))
-                            .and_then(|x| serde_json::from_str(&x).wrap_err("Failed to parse json"));
-
-                        match message {
+                        match parse_frame(message) {
                             Ok(message) => {
                                 if message.get("segments").is_some() {
+                                    if message.get("kind") == Some(&json!("result")) {
+                                        if let Some(flushed_at) = flushed_at.take() {
+                                            METRICS.final_latency.observe(flushed_at.elapsed());
+                                        }
+                                    }
                                     if message.get("kind") != Some(&json!("result")) {
                                         // If this is a result message, and we have a running shutdown timer
                                         // (i.e. we want to disconnect), we use this as the final result.
@@ -252,68 +547,77 @@ async fn handle_connection(
                                             timer.abort();
                                             shutdown_timer = None;
                                             let _ = shutdown_tx.send(());
-                                            println!("Received final result for this session in time, signalling shutdown");
+                                            debug!(parent: &session, "Received final result in time, signalling shutdown");
                                         }
                                     }
-                                    ui_sender.send(UiAction::ModelResult(message)).await.unwrap();
+                                    ui_sender.send(UiAction::ModelResult(message)).await?;
                                 } else {
-                                    eprintln!("ignoring unsolicited message: {}", message.to_string());
+                                    warn!(parent: &session, message = %redact(&message.to_string()), "Ignoring unsolicited message");
                                 }
                             },
                             Err(e) => {
-                                eprintln!("could not receive message from socket: {:#}", e);
+                                METRICS.error("connection");
+                                error!(parent: &session, "Could not receive message from socket: {e:#}");
                                 ui_sender
                                     .send(UiAction::Disconnected(Some(e.to_string())))
-                                    .await
-                                    .unwrap();
+                                    .await?;
                                 break;
                             },
                         }
                     }
                     _ = shutdown_rx.changed() => {
-                        println!("Ready to disconnect.");
+                        debug!(parent: &session, "Ready to disconnect");
                         // Processing is finished
                         shutdown_rx.mark_unchanged(); // Mark state seen
-                        ui_sender.send(UiAction::Disconnected(None)).await.unwrap();
+                        ui_sender.send(UiAction::Disconnected(None)).await?;
                         break;
                     }
                     _ = audio_rx.changed() => {
                         audio_rx.mark_unchanged(); // Mark state seen
                         let data = std::mem::take(&mut *bytes.lock().expect("Could not lock mutex to read audio data"));
+                        if last_level.elapsed() >= LEVEL_INTERVAL {
+                            last_level = Instant::now();
+                            // Dropping a level update is fine if the ui is busy
+                            let _ = ui_sender.try_send(UiAction::MicLevel(audio_level(&data)));
+                        }
 
+                        // 16-bit mono samples at 16 kHz
+                        METRICS.audio_seconds.add(data.len() as f64 / 32000.0);
                         if let Err(e) = send_audio_data(&mut socket_write, &data).await {
-                            eprintln!("could not write audio data to socket: {}", e);
+                            METRICS.error("connection");
+                            error!(parent: &session, "Could not write audio data to socket: {e}");
                             ui_sender
                                 .send(UiAction::Disconnected(Some(e.to_string())))
-                                .await
-                                .unwrap();
+                                .await?;
                             break;
                         }
                     }
                     _ = connection_receiver.changed() => {
                         // Wait until we should disconnect
                         if *connection_receiver.borrow_and_update() == ConnectionState::Disconnected {
-                            println!("Done, notifying server to finish...");
+                            debug!(parent: &session, "Done, notifying server to finish");
                             // Pause audio thread
                             *audio_active.lock().expect("Could not lock audio stop") = false;
 
                             // Don't disconnect immediately, instead instruct the server to flush
                             if let Err(e) = send_message(&mut socket_write, json!({"action": "flush"})).await {
-                                eprintln!("could not send flush action to socket: {}", e);
+                                METRICS.error("connection");
+                                error!(parent: &session, "Could not send flush action to socket: {e}");
                                 ui_sender
                                     .send(UiAction::Disconnected(Some(e.to_string())))
-                                    .await
-                                    .unwrap();
+                                    .await?;
                                 break;
                             }
+                            ui_sender.send(UiAction::Processing).await?;
+                            flushed_at = Some(Instant::now());
 
                             // If the server fails to respond within a short timeframe, we will force-kill.
                             let shutdown_tx_2 = shutdown_tx.clone();
                             let timer = runtime().spawn(async move {
                                 tokio::time::sleep(Duration::from_millis(2000)).await;
-                                println!("Server has not responded to flush, forcing disconnect now.");
+                                warn!("Server has not responded to flush, forcing disconnect now");
                                 let _ = shutdown_tx_2.send(());
-                            });
+                            }.instrument(session.clone()));
                             shutdown_timer = Some(timer);
                         } else {
                             // If the client wants to reconnect, cancel any running disconnect timers
@@ -323,67 +627,696 @@ async fn handle_connection(
                             }
                             // Restart audio thread
                             *audio_active.lock().expect("Could not lock audio stop") = true;
-                            println!("Staying connected due to user request...");
+                            debug!(parent: &session, "Staying connected due to user request");
+                            ui_sender.send(UiAction::Connected).await?;
                         }
                     }
                 };
             }
 
-            println!("Disconnecting.");
+            info!(parent: &session, "Disconnecting");
         }
 
-        // Keep the window open for another 4 seconds if no other event takes priority
-        tokio::select! {
-            _ = tokio::time::sleep(Duration::from_millis(4000)) => {
-                ui_sender.send(UiAction::HideWindow).await.unwrap();
-            },
-            _ = connection_receiver.changed() => {
-                connection_receiver.mark_changed();
-                // Early break so the request can be prioritized
-            }
-        };
+        hide_window_later(&ui_sender, &mut connection_receiver).await?;
 
-        println!("Waiting for next connection request");
+        debug!("Waiting for next connection request");
     }
 
     // Stop and join audio thread
     let _ = audio_shutdown_tx.send(());
-    audio_thread.join().expect("Could not join audio_thread");
+    if audio_thread.join().is_err() {
+        error!("The audio thread panicked");
+    }
+    Ok(())
+}
+
+/// Returns the language following `current` in the given list of languages,
+/// where "auto" stands for auto-detection. Starts from the beginning if
+/// the current language is not part of the list.
+fn next_language(languages: &[String], current: Option<&str>) -> Option<String> {
+    let current = current.unwrap_or("auto");
+    let next = languages
+        .iter()
+        .position(|x| x == current)
+        .map_or(0, |i| (i + 1) % languages.len());
+    languages.get(next).filter(|x| *x != "auto").cloned()
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Hotkeys {
+    /// Switches to the next configured language
+    language_hotkey: Option<Key>,
+    /// Starts a translating session if held while pressing a profile hotkey
+    translate_modifier: Option<Key>,
+    /// Accepts text pending review, in addition to the profile hotkey
+    review_accept: Option<Key>,
+    /// Discards text pending review
+    review_discard: Option<Key>,
+    /// Switches the overlay to the next display mode
+    display_mode_hotkey: Option<Key>,
+}
+
+impl Hotkeys {
+    fn keys(&self, profiles: &[Profile]) -> Vec<Key> {
+        profiles
+            .iter()
+            .map(|x| x.hotkey)
+            .chain(self.language_hotkey)
+            .chain(self.translate_modifier)
+            .chain(self.review_accept)
+            .chain(self.review_discard)
+            .chain(self.display_mode_hotkey)
+            .collect()
+    }
+}
+
+/// Switches the profile to the next configured language.
+async fn cycle_language(
+    profile: &mut Profile,
+    languages: &[String],
+    ui_sender: &mpsc::Sender<UiAction>,
+) {
+    if languages.is_empty() {
+        return;
+    }
+
+    let session_opts = &mut profile.session_opts;
+    session_opts.language = next_language(languages, session_opts.language.as_deref());
+    info!(
+        language = session_opts.language.as_deref().unwrap_or("auto"),
+        "Switching language"
+    );
+    let _ = ui_sender
+        .send(UiAction::LanguageChanged(session_opts.language.clone()))
+        .await;
+}
+
+/// A change of the running overlay, requested by the arguments of another invocation
+#[derive(Debug)]
+enum RemoteChange {
+    /// Makes the profile with the given index the active one
+    Profile(usize),
+    Language(Option<String>),
+    DisplayMode(DisplayMode),
+    Request(ControlRequest),
+}
+
+/// The options of the overlay which another invocation can change while it is running
+const RUNTIME_OPTIONS: &[&str] = &["language", "display_mode", "profile", "action"];
+
+fn raw_values(matches: &ArgMatches, id: &str) -> Option<Vec<OsString>> {
+    matches
+        .get_raw(id)
+        .map(|values| values.map(OsStr::to_os_string).collect())
+}
+
+/// Parses the arguments of an invocation of the overlay. Returns the changes it requests,
+/// and the options which differ from those of the running overlay but can only be changed
+/// by restarting it.
+fn remote_changes(
+    own_args: &[OsString],
+    args: &[OsString],
+    profiles: &[Profile],
+) -> Result<(Vec<RemoteChange>, Vec<String>)> {
+    let command = Cli::command();
+    let own_matches = command.clone().try_get_matches_from(own_args)?;
+    let matches = command.clone().try_get_matches_from(args)?;
+    let (Some(("overlay", own)), Some(("overlay", given))) =
+        (own_matches.subcommand(), matches.subcommand())
+    else {
+        bail!("Only the arguments of the overlay command can be passed to the running overlay");
+    };
+
+    let overlay = command
+        .find_subcommand("overlay")
+        .expect("The overlay command exists");
+    let not_applied = command
+        .get_arguments()
+        .chain(overlay.get_arguments())
+        .filter(|arg| {
+            let id = arg.get_id().as_str();
+            !RUNTIME_OPTIONS.contains(&id)
+                && given.value_source(id) == Some(ValueSource::CommandLine)
+                && raw_values(given, id) != raw_values(own, id)
+        })
+        .map(|arg| match arg.get_long() {
+            Some(long) => format!("--{long}"),
+            None => arg.get_id().to_string(),
+        })
+        .collect();
+
+    let Command::Overlay {
+        session_opts,
+        display_mode,
+        profile,
+        action,
+        ..
+    } = Cli::from_arg_matches(&matches)?.command
+    else {
+        bail!("Only the arguments of the overlay command can be passed to the running overlay");
+    };
+
+    let mut changes = vec![];
+    if let Some(name) = profile {
+        let Some(index) = profiles.iter().position(|x| x.name == name) else {
+            bail!("Unknown profile {name}");
+        };
+        changes.push(RemoteChange::Profile(index));
+    }
+    if given.value_source("language") == Some(ValueSource::CommandLine) {
+        let language = requested_language(session_opts.language);
+        changes.push(RemoteChange::Language(language));
+    }
+    if let Some(display_mode) = display_mode {
+        changes.push(RemoteChange::DisplayMode(display_mode));
+    }
+    if let Some(action) = action {
+        changes.push(RemoteChange::Request(action.into()));
+    }
+
+    Ok((changes, not_applied))
 }
 
+/// Handles a request of the control socket or the D-Bus interface
+async fn handle_control_request(
+    request: ControlRequest,
+    profile: &mut Profile,
+    languages: &[String],
+    profile_sender: &watch::Sender<Profile>,
+    connection_sender: &watch::Sender<ConnectionState>,
+    ui_sender: &mpsc::Sender<UiAction>,
+) {
+    match request {
+        ControlRequest::Start => {
+            start_session(profile, false, profile_sender, connection_sender);
+        }
+        ControlRequest::Stop => {
+            let _ = connection_sender.send(ConnectionState::Disconnected);
+        }
+        ControlRequest::Toggle => {
+            if *connection_sender.borrow() == ConnectionState::Connected {
+                let _ = connection_sender.send(ConnectionState::Disconnected);
+            } else {
+                start_session(profile, false, profile_sender, connection_sender);
+            }
+        }
+        ControlRequest::Cancel => {
+            // Discard the text first, so the final result of the session is ignored
+            let _ = ui_sender.send(UiAction::Cancel).await;
+            let _ = connection_sender.send(ConnectionState::Disconnected);
+        }
+        ControlRequest::CycleLanguage => {
+            cycle_language(profile, languages, ui_sender).await;
+        }
+        ControlRequest::CycleDisplayMode => {
+            let _ = ui_sender.send(UiAction::CycleDisplayMode).await;
+        }
+        // Answered by the control socket itself
+        ControlRequest::State | ControlRequest::Subscribe | ControlRequest::History => {}
+    }
+}
+
+fn start_session(
+    profile: &Profile,
+    translate: bool,
+    profile_sender: &watch::Sender<Profile>,
+    connection_sender: &watch::Sender<ConnectionState>,
+) {
+    let mut profile = profile.clone();
+    if translate {
+        profile.session_opts.task = Some(Task::Translate);
+    }
+    let _ = profile_sender.send(profile);
+    let _ = connection_sender.send(ConnectionState::Connected);
+    // window will be hidden as soon as connection task is ready
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn handle_hotkey(
     mut hotkey_receiver: mpsc::Receiver<HotkeyEvent>,
+    mut control_receiver: mpsc::Receiver<ControlRequest>,
+    mut remote_receiver: mpsc::Receiver<RemoteChange>,
     connection_sender: watch::Sender<ConnectionState>,
+    profile_sender: watch::Sender<Profile>,
+    ui_sender: mpsc::Sender<UiAction>,
+    review_pending: watch::Receiver<bool>,
+    mut profiles: Vec<Profile>,
+    hotkeys: Hotkeys,
+    languages: Vec<String>,
 ) {
-    while let Some(event) = hotkey_receiver.recv().await {
+    let mut translate_modifier_held = false;
+    // Set if the profile hotkey was pressed to accept a review, so its release is ignored
+    let mut accept_held = false;
+    // The profile that was used last. Language switching applies to this profile.
+    let mut active = 0;
+
+    loop {
+        let event = tokio::select! {
+            Some(event) = hotkey_receiver.recv() => event,
+            Some(request) = control_receiver.recv() => {
+                handle_control_request(request, &mut profiles[active], &languages, &profile_sender, &connection_sender, &ui_sender).await;
+                continue;
+            }
+            Some(change) = remote_receiver.recv() => {
+                match change {
+                    RemoteChange::Profile(index) => {
+                        info!(profile = %profiles[index].name, "Switching profile");
+                        active = index;
+                    }
+                    RemoteChange::Language(language) => {
+                        info!(language = language.as_deref().unwrap_or("auto"), "Switching language");
+                        profiles[active].session_opts.language = language.clone();
+                        let _ = ui_sender.send(UiAction::LanguageChanged(language)).await;
+                    }
+                    RemoteChange::DisplayMode(mode) => {
+                        let _ = ui_sender.send(UiAction::SetDisplayMode(mode)).await;
+                    }
+                    RemoteChange::Request(request) => {
+                        handle_control_request(request, &mut profiles[active], &languages, &profile_sender, &connection_sender, &ui_sender).await;
+                    }
+                }
+                continue;
+            }
+            else => break,
+        };
+
         match event {
-            HotkeyEvent::Pressed => {
-                let _ = connection_sender.send(ConnectionState::Connected);
-                // window will be hidden as soon as connection task is ready
+            HotkeyEvent::Pressed(key) if Some(key) == hotkeys.translate_modifier => {
+                translate_modifier_held = true;
+            }
+            HotkeyEvent::Released(key) if Some(key) == hotkeys.translate_modifier => {
+                translate_modifier_held = false;
+            }
+            HotkeyEvent::Pressed(key) if Some(key) == hotkeys.language_hotkey => {
+                cycle_language(&mut profiles[active], &languages, &ui_sender).await;
+            }
+            HotkeyEvent::Pressed(key) if Some(key) == hotkeys.display_mode_hotkey => {
+                let _ = ui_sender.send(UiAction::CycleDisplayMode).await;
             }
-            HotkeyEvent::Released => {
+            HotkeyEvent::Pressed(key)
+                if *review_pending.borrow() && Some(key) == hotkeys.review_discard =>
+            {
+                let _ = ui_sender.send(UiAction::DiscardReview).await;
+            }
+            HotkeyEvent::Pressed(key)
+                if *review_pending.borrow()
+                    && (Some(key) == hotkeys.review_accept || key == profiles[active].hotkey) =>
+            {
+                accept_held = key == profiles[active].hotkey;
+                let _ = ui_sender.send(UiAction::AcceptReview).await;
+            }
+            HotkeyEvent::Pressed(key) => {
+                let Some(index) = profiles.iter().position(|x| x.hotkey == key) else {
+                    continue;
+                };
+
+                active = index;
+                start_session(
+                    &profiles[index],
+                    translate_modifier_held,
+                    &profile_sender,
+                    &connection_sender,
+                );
+            }
+            HotkeyEvent::Released(key) if key == profiles[active].hotkey => {
+                if std::mem::take(&mut accept_held) {
+                    continue;
+                }
+
                 let _ = connection_sender.send(ConnectionState::Disconnected);
                 // window will be hidden as soon as transcription task is finished
             }
+            _ => {}
         }
     }
 }
 
-pub fn launch_app(opts: Command) -> Result<()> {
-    // Create a new application
-    let app = Application::builder().application_id(APP_ID).build();
+/// Sends the final text of an utterance to the given output.
+async fn output_text(
+    output: OutputSink,
+    text: String,
+    virtual_keyboard_sender: &KeyboardSender,
+    sounds: &SoundCues,
+    ui_sender: &mpsc::Sender<UiAction>,
+) {
+    if text.is_empty() {
+        return;
+    }
+    if output != OutputSink::None {
+        sounds.play(Cue::Result);
+    }
 
-    let Command::Overlay { style, .. } = opts.clone() else {
+    match output {
+        OutputSink::Type => {
+            METRICS
+                .words_typed
+                .add(text.split_whitespace().count() as f64);
+            if let Err(e) = virtual_keyboard_sender
+                .send(KeyboardAction::Type(text))
+                .await
+            {
+                report_error(ui_sender, e);
+            }
+        }
+        OutputSink::Stdout => {
+            print!("{text}");
+            let _ = std::io::stdout().flush();
+        }
+        OutputSink::None => {}
+    }
+}
+
+/// Shows an error in the overlay that doesn't end the session. Errors are dropped
+/// if the user interface is busy, as they are logged anyway.
+fn report_error(ui_sender: &mpsc::Sender<UiAction>, error: impl std::fmt::Display) {
+    let _ = ui_sender.try_send(UiAction::Error(error.to_string()));
+}
+
+/// Announces an error with a sound and a notification, unless the
+/// same error was already announced within `ERROR_REPEAT_INTERVAL`.
+fn announce_error(
+    last_error: &mut Option<(String, Instant)>,
+    summary: &str,
+    error: &str,
+    sounds: &SoundCues,
+    notifier: &Notifier,
+) {
+    let repeated = last_error
+        .as_ref()
+        .is_some_and(|(last, at)| last == error && at.elapsed() < ERROR_REPEAT_INTERVAL);
+    if repeated {
+        return;
+    }
+
+    *last_error = Some((error.to_string(), Instant::now()));
+    sounds.play(Cue::Error);
+    if notifier.opts().errors {
+        notifier.notify(summary, error, Urgency::Critical);
+    }
+}
+
+/// Adds the given text to the history of the control socket, newest first.
+fn record_transcript(history: &watch::Sender<Vec<String>>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+
+    debug!(text = %redact(text), "Transcribed text");
+    history.send_modify(|x| {
+        x.insert(0, text.to_string());
+        x.truncate(HISTORY_SIZE);
+    });
+}
+
+/// Returns the text of a line, without leading whitespace.
+fn words_text(words: &[Word]) -> String {
+    let text: String = words.iter().map(|word| word.word.as_str()).collect();
+    text.trim_start().to_string()
+}
+
+/// Renders the words of a line colored by their probability. Words below the
+/// confidence threshold are underlined and the selected word is highlighted.
+fn words_markup(words: &[Word], theme: &Theme, threshold: f32, selected: Option<usize>) -> String {
+    let mut markup = String::new();
+    for (i, word) in words.iter().enumerate() {
+        let text = word.word.trim_start();
+        if i != 0 {
+            markup += &word.word[..word.word.len() - text.len()];
+        }
+
+        let mut attributes = String::new();
+        if let Some(color) = theme.word_color(word.probability) {
+            attributes += &format!(" color=\"{color}\"");
+        }
+        if word.probability < threshold {
+            attributes += " underline=\"error\"";
+        }
+        if selected == Some(i) {
+            attributes += &format!(" background=\"{}\"", theme.selection);
+        }
+        markup += &format!(
+            "<span{attributes}>{}</span>",
+            glib::markup_escape_text(text)
+        );
+    }
+
+    markup
+}
+
+fn review_markup(
+    lines: &[Vec<Word>],
+    selected: Option<(usize, usize)>,
+    theme: &Theme,
+    threshold: f32,
+) -> Vec<String> {
+    lines
+        .iter()
+        .enumerate()
+        .map(|(li, words)| {
+            let selected = selected.filter(|x| x.0 == li).map(|x| x.1);
+            words_markup(words, theme, threshold, selected)
+        })
+        .collect()
+}
+
+/// Returns the (line, word) indices of all words below the confidence threshold.
+fn low_confidence_words(lines: &[Vec<Word>], threshold: f32) -> Vec<(usize, usize)> {
+    lines
+        .iter()
+        .enumerate()
+        .flat_map(|(li, words)| {
+            words
+                .iter()
+                .enumerate()
+                .filter(|(_, word)| word.probability < threshold)
+                .map(move |(wi, _)| (li, wi))
+        })
+        .collect()
+}
+
+/// Renders the language indicator shown next to the connection status.
+/// If the language is auto-detected, the detected language is shown if known.
+fn language_markup(
+    theme: &Theme,
+    language: Option<&str>,
+    detected_language: Option<&str>,
+) -> String {
+    let text = match (language, detected_language) {
+        (Some(language), _) => language.to_string(),
+        (None, Some(detected_language)) => format!("auto: {detected_language}"),
+        (None, None) => "auto".to_string(),
+    };
+    format!(
+        " <span color='{}'>[{}]</span>",
+        theme.muted,
+        glib::markup_escape_text(&text)
+    )
+}
+
+pub fn launch_app(
+    opts: Command,
+    control_listener: Option<std::os::unix::net::UnixListener>,
+) -> Result<()> {
+    // Create a new application. Only one instance runs at a time, further
+    // invocations forward their arguments to it over D-Bus.
+    let app = Application::builder()
+        .application_id(APP_ID)
+        .flags(gio::ApplicationFlags::HANDLES_COMMAND_LINE)
+        .build();
+
+    let Command::Overlay {
+        connection_opts,
+        session_opts,
+        config,
+        style,
+        theme,
+        display_mode,
+        // Applied by the command line handler, like the arguments of further invocations
+        profile: _,
+        action: _,
+        hotkey,
+        languages,
+        language_hotkey,
+        translate_modifier,
+        display_mode_hotkey,
+        typing_backend,
+        key_delay_ms,
+        live_typing,
+        review,
+        notifications,
+        sounds,
+    } = opts
+    else {
         bail!("got invalid command options");
     };
+    let config = load_config(config.as_deref())?;
 
-    // Connect to signals
-    app.connect_startup(move |_| load_css(style.clone()));
-    app.connect_activate(move |app| build_ui(app, opts.clone()));
+    let default_profile = Profile {
+        name: "default".to_string(),
+        hotkey,
+        connection_opts,
+        session_opts: session_opts.or(&config.session),
+        output: config.output,
+        post_process: config.post_process.clone(),
+        review: ReviewOpts {
+            enabled: review || config.review.enabled,
+            ..config.review.clone()
+        },
+        live_typing: LiveTypingOpts {
+            enabled: live_typing || config.live_typing.enabled,
+            ..config.live_typing.clone()
+        },
+    };
+    let profiles = resolve_profiles(default_profile, &config)?;
+
+    let hotkeys = Hotkeys {
+        language_hotkey: language_hotkey
+            .map(Ok)
+            .or_else(|| config.language_hotkey.as_deref().map(parse_key))
+            .transpose()?,
+        translate_modifier: translate_modifier
+            .map(Ok)
+            .or_else(|| config.translate_modifier.as_deref().map(parse_key))
+            .transpose()?,
+        review_accept: config
+            .review_accept_key
+            .as_deref()
+            .map(parse_key)
+            .transpose()?,
+        review_discard: Some(parse_key(
+            config.review_discard_key.as_deref().unwrap_or("KEY_ESC"),
+        )?),
+        display_mode_hotkey: display_mode_hotkey
+            .map(Ok)
+            .or_else(|| config.display_mode_hotkey.as_deref().map(parse_key))
+            .transpose()?,
+    };
+    let languages = if languages.is_empty() {
+        config.languages
+    } else {
+        languages
+    };
+    let rules = config.rules;
+    let mut overlay_opts = config.overlay;
+    if let Some(theme) = theme {
+        overlay_opts.theme.base = theme;
+    }
+    if let Some(display_mode) = display_mode {
+        overlay_opts.display_mode = display_mode;
+    }
+    // Fail early if the theme contains invalid colors
+    Theme::new(&overlay_opts.theme)?;
+    let notification_opts = NotificationOpts {
+        enabled: notifications || config.notifications.enabled,
+        ..config.notifications
+    };
+    let sound_opts = SoundOpts {
+        enabled: sounds || config.sounds.enabled,
+        ..config.sounds
+    };
+    let typing_backend = typing_backend.unwrap_or(config.typing_backend);
+    let typing_opts = TypingOpts {
+        key_delay_ms: key_delay_ms.unwrap_or(config.typing.key_delay_ms),
+        ..config.typing
+    };
+    let metrics_listen = config.metrics.listen;
+    let client_name = config.client_name;
+
+    // Connect to signals, startup and activate are only emitted in the primary instance
+    app.connect_startup(move |_| {
+        load_css(style.clone());
+        if let Some(listen) = metrics_listen.clone() {
+            runtime().spawn(async move {
+                if let Err(e) = crate::metrics::serve(&listen).await {
+                    error!("Metrics endpoint failed: {e:#}");
+                }
+            });
+        }
+    });
+    let own_args: Vec<OsString> = std::env::args_os().collect();
+    let args: Vec<String> = own_args
+        .iter()
+        .map(|x| x.to_string_lossy().into_owned())
+        .collect();
+    // Further invocations forward their arguments to the running overlay, which applies
+    // the options that can change at runtime
+    let (remote_sender, remote_receiver) = mpsc::channel(16);
+    let command_line_profiles = profiles.clone();
+    app.connect_command_line(move |app, command_line| {
+        let remote = command_line.is_remote();
+        if !remote {
+            app.activate();
+        }
+
+        let forwarded = command_line.arguments();
+        let (changes, not_applied) =
+            match remote_changes(&own_args, &forwarded, &command_line_profiles) {
+                Ok(result) => result,
+                Err(e) => {
+                    warn!(arguments = ?forwarded, "Rejecting invalid arguments: {e}");
+                    command_line.printerr_literal(&format!("{e}\n"));
+                    return ExitCode::FAILURE;
+                }
+            };
+        for change in changes {
+            // The other options were already used to set up the overlay
+            if !remote && !matches!(change, RemoteChange::Profile(_) | RemoteChange::Request(_)) {
+                continue;
+            }
+            if remote_sender.try_send(change).is_err() {
+                command_line.printerr_literal("The overlay is busy, try again later\n");
+                return ExitCode::FAILURE;
+            }
+        }
+
+        if not_applied.is_empty() {
+            ExitCode::SUCCESS
+        } else {
+            let not_applied = not_applied.join(", ");
+            warn!(options = %not_applied, "Not applying options which can't change at runtime");
+            command_line.printerr_literal(&format!(
+                "whisper-overlay is already running, restart it to change {not_applied}\n"
+            ));
+            ExitCode::FAILURE
+        }
+    });
+    let remote_receiver = Cell::new(Some(remote_receiver));
+    let control_listener = Cell::new(control_listener);
+    app.connect_activate(move |app| {
+        // Activating the running instance again, e.g. over D-Bus, must not create a second overlay
+        if !app.windows().is_empty() {
+            return;
+        }
+
+        let Some(remote_receiver) = remote_receiver.take() else {
+            return;
+        };
+        build_ui(
+            app,
+            control_listener.take(),
+            remote_receiver,
+            profiles.clone(),
+            hotkeys,
+            languages.clone(),
+            rules.clone(),
+            typing_backend,
+            typing_opts.clone(),
+            overlay_opts.clone(),
+            notification_opts.clone(),
+            sound_opts.clone(),
+            client_name.clone(),
+        )
+    });
 
     // Run the application
-    let exit_code = app.run_with_args::<&str>(&[]);
+    app.register(gio::Cancellable::NONE)
+        .wrap_err("Could not register the application")?;
+    let remote = app.is_remote();
+    let exit_code = app.run_with_args(&args);
     if exit_code != ExitCode::SUCCESS {
+        if remote {
+            bail!("Not all arguments could be applied to the running overlay");
+        }
         bail!("Could not launch gtk application: {:?}", exit_code);
     };
 
@@ -407,49 +1340,26 @@ fn load_css(style: Option<PathBuf>) {
     );
 }
 
-fn build_ui(app: &Application, opts: Command) {
-    let Command::Overlay {
-        connection_opts,
-        hotkey,
-        ..
-    } = opts
-    else {
-        panic!("build_ui() got invalid command options");
-    };
-
-    let main_box = gtk::Box::builder()
-        .orientation(gtk::Orientation::Vertical)
-        .spacing(5)
-        .can_target(false)
-        .can_focus(false)
-        .focus_on_click(false)
-        .build();
-    main_box.add_css_class("main-box");
-
-    let live_text = Label::builder()
-        .wrap(true)
-        .wrap_mode(gdk::pango::WrapMode::WordChar)
-        .justify(gtk::Justification::Left)
-        .halign(gtk::Align::Start)
-        .can_target(false)
-        .can_focus(false)
-        .focus_on_click(false)
-        .build();
-    live_text.add_css_class("live-text");
-    live_text.add_tick_callback(move |widget, _| {
-        widget.queue_draw();
-        glib::ControlFlow::Continue
-    });
-    main_box.append(&live_text);
-
-    let status_label = Label::builder()
-        .halign(gtk::Align::Start)
-        .can_target(false)
-        .can_focus(false)
-        .focus_on_click(false)
-        .build();
-    status_label.add_css_class("connection-status");
-    main_box.append(&status_label);
+#[allow(clippy::too_many_arguments)]
+fn build_ui(
+    app: &Application,
+    control_listener: Option<std::os::unix::net::UnixListener>,
+    remote_receiver: mpsc::Receiver<RemoteChange>,
+    profiles: Vec<Profile>,
+    hotkeys: Hotkeys,
+    languages: Vec<String>,
+    rules: Vec<AppRule>,
+    typing_backend: TypingBackend,
+    typing_opts: TypingOpts,
+    overlay_opts: OverlayOpts,
+    notification_opts: NotificationOpts,
+    sound_opts: SoundOpts,
+    client_name: Option<String>,
+) {
+    let theme = Theme::new(&overlay_opts.theme).expect("Theme was validated on startup");
+    let notifier = Notifier::new(notification_opts);
+    let sounds = SoundCues::new(sound_opts);
+    let mut widgets = OverlayWidgets::new(overlay_opts.display_mode, &theme, &overlay_opts);
 
     // Create a new window and present it
     let window = ApplicationWindow::builder()
@@ -460,137 +1370,318 @@ fn build_ui(app: &Application, opts: Command) {
         .resizable(false)
         .can_target(false)
         .focusable(false)
-        .default_width(1600)
         .default_height(0)
-        .child(&main_box)
+        .child(&widgets.root)
         .build();
 
     window.init_layer_shell();
-    window.set_layer(Layer::Overlay);
     window.set_keyboard_mode(gtk_layer_shell::KeyboardMode::None);
-    window.set_anchor(gtk_layer_shell::Edge::Bottom, true);
-    window.set_margin(gtk_layer_shell::Edge::Bottom, 200);
+    init_placement(&window, &overlay_opts);
     window.set_namespace("whisper-overlay");
 
     window.connect_realize(|window| {
-        let wayland_surface = window.surface().and_downcast::<WaylandSurface>().unwrap();
-        wayland_surface.set_input_region(&Region::create_rectangle(&RectangleInt::new(0, 0, 0, 0)));
+        match window.surface().and_downcast::<WaylandSurface>() {
+            Some(surface) => {
+                surface.set_input_region(&Region::create_rectangle(&RectangleInt::new(0, 0, 0, 0)))
+            }
+            None => {
+                error!("The overlay is not a wayland surface, it will not let clicks pass through")
+            }
+        }
         window.set_visible(false);
     });
 
     window.present();
+    crate::service::notify_ready();
+    crate::service::start_watchdog();
 
     let (ui_sender, mut ui_receiver) = mpsc::channel(64);
     let (connection_sender, connection_receiver) = watch::channel(ConnectionState::Disconnected);
+    let (profile_sender, profile_receiver) = watch::channel(profiles[0].clone());
     let (hotkey_sender, hotkey_receiver) = mpsc::channel(64);
-    let (virtual_keyboard_sender, virtual_keyboard_receiver) = mpsc::channel(64);
+    let (review_sender, review_receiver) = watch::channel(false);
+    let (display_mode_sender, display_mode_receiver) = watch::channel(overlay_opts.display_mode);
 
     // Spawn connection manager
+    let output_selection = overlay_opts.output.clone();
     runtime().spawn(
         glib::clone!(@strong connection_receiver, @strong ui_sender => async move {
-            handle_connection(connection_receiver, ui_sender, connection_opts.clone()).await;
+            if let Err(e) = handle_connection(connection_receiver, ui_sender, profile_receiver, rules, output_selection, display_mode_receiver, client_name).await {
+                error!("Connection manager stopped: {e}");
+            }
         }),
     );
 
+    // Spawn control socket
+    let (state_sender, state_receiver) = watch::channel(OverlayState {
+        profile: profiles[0].name.clone(),
+        language: profiles[0].session_opts.language.clone(),
+        ..OverlayState::default()
+    });
+    let (history_sender, history_receiver) = watch::channel(vec![]);
+    let (control_sender, control_receiver) = mpsc::channel(16);
+    if let Err(e) = crate::dbus::register(
+        app,
+        state_receiver.clone(),
+        history_receiver.clone(),
+        control_sender.clone(),
+    ) {
+        warn!("Could not export the D-Bus interface: {e:#}");
+    }
+    runtime().spawn(async move {
+        if let Err(e) = crate::control::serve(
+            control_listener,
+            state_receiver,
+            history_receiver,
+            control_sender,
+        )
+        .await
+        {
+            error!("Control socket failed: {e:#}");
+        }
+    });
+
     // Spawn hotkey detector
-    runtime().spawn(glib::clone!(@strong hotkey_sender => async move {
-        crate::hotkeys::register_and_watch(hotkey_sender, hotkey).await;
-    }));
+    let keys = hotkeys.keys(&profiles);
+    runtime().spawn(
+        glib::clone!(@strong hotkey_sender, @strong ui_sender => async move {
+            if let Err(e) = crate::hotkeys::register_and_watch(hotkey_sender, keys).await {
+                let error = format!("{e}, new input devices will not be detected");
+                warn!("{error}");
+                let _ = ui_sender.send(UiAction::Error(error)).await;
+            }
+        }),
+    );
 
     // Spawn hotkey processor
-    runtime().spawn(glib::clone!(@strong connection_sender => async move {
-        handle_hotkey(hotkey_receiver, connection_sender).await;
+    runtime().spawn(
+        glib::clone!(@strong connection_sender, @strong profile_sender, @strong ui_sender, @strong profiles => async move {
+            handle_hotkey(hotkey_receiver, control_receiver, remote_receiver, connection_sender, profile_sender, ui_sender, review_receiver, profiles, hotkeys, languages).await;
+        }),
+    );
+
+    // Typing happens on its own thread, its failures are shown in the overlay
+    let (typing_error_sender, mut typing_error_receiver) = mpsc::channel(8);
+    let virtual_keyboard_sender =
+        spawn_virtual_keyboard(typing_backend, typing_opts, typing_error_sender);
+    runtime().spawn(glib::clone!(@strong ui_sender => async move {
+        while let Some(error) = typing_error_receiver.recv().await {
+            let _ = ui_sender.send(UiAction::Error(error)).await;
+        }
     }));
 
-    spawn_virtual_keyboard(virtual_keyboard_receiver).expect("Failed to spawn virutal keyboard");
+    // Review key handling, only active while the window has keyboard focus
+    let key_controller = gtk::EventControllerKey::new();
+    key_controller.connect_key_pressed(glib::clone!(@strong ui_sender => move |_, key, _, _| {
+        let action = match key {
+            gdk::Key::Left | gdk::Key::ISO_Left_Tab => UiAction::SelectWord(-1),
+            gdk::Key::Right | gdk::Key::Tab => UiAction::SelectWord(1),
+            gdk::Key::Up => UiAction::CycleAlternative(-1),
+            gdk::Key::Down => UiAction::CycleAlternative(1),
+            gdk::Key::Return | gdk::Key::KP_Enter => UiAction::AcceptReview,
+            gdk::Key::Escape => UiAction::DiscardReview,
+            gdk::Key::r => UiAction::Redictate(true),
+            _ => return glib::Propagation::Proceed,
+        };
+        let _ = ui_sender.try_send(action);
+        glib::Propagation::Stop
+    }));
+    key_controller.connect_key_released(glib::clone!(@strong ui_sender => move |_, key, _, _| {
+        if key == gdk::Key::r {
+            let _ = ui_sender.try_send(UiAction::Redictate(false));
+        }
+    }));
+    window.add_controller(key_controller);
 
     // Ui updater
     glib::spawn_future_local(async move {
-        let keep_duration = Duration::from_millis(6000);
-        let mut line_history: Vec<(SystemTime, String)> = vec![];
-
-        let gradient = colorgrad::CustomGradient::new()
-            .html_colors(&[
-                "#fe0000", "#fb3209", "#f74811", "#f35918", "#ef671e", "#ea7423", "#e67f28",
-                "#e18a2c", "#dc9430", "#d79e34", "#d1a738", "#cbb03b", "#c4b93d", "#bcc23e",
-                "#b2cc3d", "#a6d53a", "#97df36", "#82e92e", "#62f321", "#00ff00",
-            ])
-            .build()
-            .expect("Could not build color gradient");
+        let mut status = String::new();
+        // The last error reported outside of the connection, shown after the status
+        let mut status_error: Option<String> = None;
+        let mut profile = profiles[0].clone();
+        let mut detected_language: Option<String> = None;
+        let mut live_typer = LiveTyper::new(profile.live_typing.stable_updates);
+        // The lines pending review, and the selected low confidence word as (line, word) index
+        let mut review_lines: Vec<Vec<Word>> = vec![];
+        let mut review_selected: Option<(usize, usize)> = None;
+        // The word which is currently dictated again
+        let mut redictating: Option<(usize, usize)> = None;
+        let mut review_id: u64 = 0;
+        let mut hide_after_review = false;
+        let mut placement = Placement::default();
+        // Identifies the current wait for the model lock, to detect long waits
+        let mut lock_id: u64 = 0;
+        let mut session_state = SessionState::Idle;
+        let mut session_error: Option<String> = None;
+        // The last announced error, to not repeat sounds and notifications for it
+        let mut last_error: Option<(String, Instant)> = None;
+        let mut queue_position: Option<u32> = None;
+        // Set if the current session was cancelled, so its results are ignored
+        let mut cancelled = false;
+
+        let threshold = overlay_opts.low_confidence_threshold;
 
         while let Some(ui_action) = ui_receiver.recv().await {
+            let mut review_accepted = None;
+            let mut review_changed = false;
             match ui_action {
+                UiAction::ModelResult(_) if cancelled => {}
                 UiAction::ModelResult(value) => {
                     match serde_json::from_value::<ModelResult>(value) {
                         Ok(res) => {
-                            let now = SystemTime::now();
-
-                            // Expire old history
-                            line_history.retain(|&(time, _)| {
-                                now.duration_since(time)
-                                    .map_or(false, |x| x <= keep_duration)
-                            });
-
-                            let mut to_type = "".to_string();
-                            let mut line_markup = "".to_string();
-                            let mut markup = line_history
+                            let lines = res
+                                .segments
+                                .iter()
+                                .map(|segment| words_text(&segment.words))
+                                .collect::<Vec<String>>();
+                            let line_markup = res
+                                .segments
                                 .iter()
-                                .map(|(_, markup)| markup)
-                                .cloned()
+                                .map(|segment| {
+                                    words_markup(&segment.words, &theme, threshold, None)
+                                })
                                 .collect::<Vec<String>>()
                                 .join("\n");
+                            widgets.set_realtime(&line_markup);
 
-                            for (si, segment) in res.segments.iter().enumerate() {
-                                if si != 0 {
-                                    line_markup += "\n";
-                                }
-
-                                for (wi, word) in segment.words.iter().enumerate() {
-                                    let color = gradient.at(word.probability.into());
-                                    let word = if wi == 0 {
-                                        word.word.trim_start()
-                                    } else {
-                                        &word.word
-                                    };
-
-                                    //let rgba = color.to_rgba8();
-                                    //print!("{}", word.color(Rgb(rgba[0], rgba[1], rgba[2])));
-                                    //let _ = std::io::stdout().flush();
-
-                                    to_type += &word;
-                                    line_markup += &format!(
-                                        "<span color=\"{fg}\">{text}</span>",
-                                        fg = color.to_hex_string(),
-                                        text = glib::markup_escape_text(word)
-                                    );
-                                }
-
-                                to_type = to_type.trim_end().to_string() + "\n";
+                            if profile.session_opts.language.is_none() && res.language.is_some() {
+                                detected_language = res.language;
                             }
 
-                            if !markup.is_empty() {
-                                markup += "\n";
+                            let review =
+                                profile.review.enabled && profile.output != OutputSink::None;
+                            let live_typing = profile.live_typing.enabled
+                                && profile.output == OutputSink::Type
+                                && !review;
+                            if live_typing && res.kind != "result" {
+                                let words = res
+                                    .segments
+                                    .iter()
+                                    .flat_map(|segment| &segment.words)
+                                    .map(|word| LiveWord {
+                                        text: word.word.clone(),
+                                        begin: word.begin,
+                                    })
+                                    .collect();
+                                if let Some(text) = live_typer.update(words) {
+                                    if let Err(e) = virtual_keyboard_sender
+                                        .send(KeyboardAction::Type(text))
+                                        .await
+                                    {
+                                        report_error(&ui_sender, e);
+                                    }
+                                }
                             }
-                            markup += &line_markup;
-                            live_text.set_markup(&markup);
 
                             // Add line to history if we have a result
                             if res.kind == "result" {
-                                if !to_type.is_empty() {
-                                    let _ = virtual_keyboard_sender.send(to_type).await;
+                                widgets.set_realtime("");
+                                let to_type = post_process(&lines, &profile.post_process);
+                                if let Some((li, wi)) = redictating.take() {
+                                    // Replace the selected word with everything that was said
+                                    let mut words: Vec<Word> =
+                                        res.segments.into_iter().flat_map(|x| x.words).collect();
+                                    if let (Some(line), Some(first)) =
+                                        (review_lines.get_mut(li), words.first_mut())
+                                    {
+                                        if wi < line.len() {
+                                            let original = &line[wi].word;
+                                            let space = &original
+                                                [..original.len() - original.trim_start().len()];
+                                            first.word =
+                                                format!("{space}{}", first.word.trim_start());
+                                            line.splice(wi..=wi, words);
+                                        }
+                                    }
+                                    review_selected = None;
+                                    review_changed = true;
+                                } else if review {
+                                    if !to_type.is_empty() {
+                                        review_lines
+                                            .extend(res.segments.into_iter().map(|x| x.words));
+                                        widgets.set_pending(
+                                            &review_markup(
+                                                &review_lines,
+                                                review_selected,
+                                                &theme,
+                                                threshold,
+                                            )
+                                            .join("\n"),
+                                        );
+                                        review_id += 1;
+                                        let _ = review_sender.send(true);
+                                        if let Some(timeout) = profile.review.timeout_ms {
+                                            let ui_sender = ui_sender.clone();
+                                            let id = review_id;
+                                            runtime().spawn(async move {
+                                                tokio::time::sleep(Duration::from_millis(timeout))
+                                                    .await;
+                                                let _ = ui_sender
+                                                    .send(UiAction::ReviewTimeout(id))
+                                                    .await;
+                                            });
+                                        }
+                                    }
+                                } else if live_typing {
+                                    if !to_type.is_empty() {
+                                        sounds.play(Cue::Result);
+                                    }
+                                    record_transcript(&history_sender, &to_type);
+                                    METRICS
+                                        .words_typed
+                                        .add(to_type.split_whitespace().count() as f64);
+                                    let (erase, rest) = live_typer.finish(&to_type);
+                                    if erase > 0 {
+                                        if let Err(e) = virtual_keyboard_sender
+                                            .send(KeyboardAction::Erase(erase))
+                                            .await
+                                        {
+                                            report_error(&ui_sender, e);
+                                        }
+                                    }
+                                    if !rest.is_empty() {
+                                        if let Err(e) = virtual_keyboard_sender
+                                            .send(KeyboardAction::Type(rest))
+                                            .await
+                                        {
+                                            report_error(&ui_sender, e);
+                                        }
+                                    }
+                                    widgets.push_final(line_markup);
+                                } else {
+                                    record_transcript(&history_sender, &to_type);
+                                    output_text(
+                                        profile.output,
+                                        to_type,
+                                        &virtual_keyboard_sender,
+                                        &sounds,
+                                        &ui_sender,
+                                    )
+                                    .await;
+                                    widgets.push_final(line_markup);
                                 }
-                                line_history.push((now, line_markup))
                             }
                         }
-                        Err(e) => eprintln!("error: ignoring invalid model result data: {e}"),
+                        Err(e) => {
+                            METRICS.error("protocol");
+                            warn!("Ignoring invalid model result data: {e}");
+                        }
                     }
                 }
+                UiAction::HideWindow if !review_lines.is_empty() => {
+                    // Keep the text visible until the review is finished
+                    hide_after_review = true;
+                }
                 UiAction::HideWindow => {
                     window.set_visible(false);
                     window.queue_draw();
-                    live_text.set_markup("");
+                    widgets.clear();
+                    widgets.set_level(0.0);
                 }
-                UiAction::ShowWindow => {
+                UiAction::ShowWindow(new_placement) => {
+                    placement = new_placement;
+                    place_window(&window, &overlay_opts, widgets.mode, &placement);
+
                     // Just don't ask, this is not an oversight!
                     // If the window is not toggled, on, off, on, it won't show the first time.
                     // This is somehow related to hiding the window in connect_realize.
@@ -598,29 +1689,409 @@ fn build_ui(app: &Application, opts: Command) {
                     window.set_visible(false);
                     window.set_visible(true);
                     window.queue_draw();
-                    status_label.queue_draw();
+                    hide_after_review = false;
                 }
                 UiAction::Disconnected(reason) => {
-                    let mut message = "<span color='gray'></span> Disconnected".to_string();
+                    lock_id += 1;
+                    status = theme.status_markup(Status::Disconnected, "Disconnected");
+                    session_state = match reason {
+                        Some(_) => SessionState::Error,
+                        None => SessionState::Idle,
+                    };
+                    session_error = reason.clone();
                     if let Some(reason) = reason {
-                        message += &format!(" <span color='gray'>{}</span>", reason);
+                        status += &format!(
+                            " <span color='{}'>{}</span>",
+                            theme.muted,
+                            glib::markup_escape_text(&reason)
+                        );
+                        announce_error(
+                            &mut last_error,
+                            "Speech recognition failed",
+                            &reason,
+                            &sounds,
+                            &notifier,
+                        );
                     }
-                    status_label.set_markup(&message);
-                    status_label.queue_draw();
+                }
+                UiAction::Error(error) => {
+                    status_error = Some(error.clone());
+                    session_error = Some(error.clone());
+                    announce_error(
+                        &mut last_error,
+                        "Whisper overlay error",
+                        &error,
+                        &sounds,
+                        &notifier,
+                    );
+                }
+                UiAction::Refused(reason) => {
+                    status = theme.status_markup(Status::Disconnected, "Disconnected");
+                    status += &format!(
+                        " <span color='{}'>{}</span>",
+                        theme.muted,
+                        glib::markup_escape_text(&reason)
+                    );
+                    session_state = SessionState::Idle;
+                    session_error = None;
                 }
                 UiAction::Connecting => {
-                    status_label.set_markup("<span color='yellow'></span> Connecting");
-                    status_label.queue_draw();
+                    status = theme.status_markup(Status::Connecting, "Connecting");
+                    status_error = None;
+                    session_state = SessionState::Connecting;
+                    session_error = None;
+                    detected_language = None;
                 }
                 UiAction::Locking => {
-                    status_label.set_markup("<span color='orange'></span> Waiting for model lock");
-                    status_label.queue_draw();
+                    status = theme.status_markup(Status::Locking, "Waiting for model lock");
+                    session_state = SessionState::Locking;
+                    queue_position = None;
+                    lock_id += 1;
+                    let lock_wait_ms = notifier.opts().lock_wait_ms;
+                    if lock_wait_ms > 0 {
+                        let ui_sender = ui_sender.clone();
+                        let id = lock_id;
+                        runtime().spawn(async move {
+                            tokio::time::sleep(Duration::from_millis(lock_wait_ms)).await;
+                            let _ = ui_sender.send(UiAction::LockWaitTimeout(id)).await;
+                        });
+                    }
+                }
+                UiAction::Queued(position, eta) => {
+                    let text = format!("Queued ({})", queue_text(position, eta));
+                    status = theme.status_markup(Status::Locking, &text);
+                    queue_position = Some(position);
+                }
+                UiAction::LockWaitTimeout(id) => {
+                    if id == lock_id {
+                        let position = match queue_position {
+                            Some(position) => {
+                                format!(" You are {} in the queue.", queue_text(position, None))
+                            }
+                            None => String::new(),
+                        };
+                        notifier.notify(
+                            "Waiting for the model",
+                            &format!("The server is busy with another session, recording starts once it is done.{position} Release the hotkey to stop waiting."),
+                            Urgency::Normal,
+                        );
+                    }
                 }
                 UiAction::Connected => {
-                    status_label.set_markup("<span color='#4ab0fa'></span> Connected");
-                    status_label.queue_draw();
+                    lock_id += 1;
+                    status = theme.status_markup(Status::Connected, "Connected");
+                    session_state = SessionState::Recording;
+                    // Errors and lock waits of earlier sessions are resolved now
+                    notifier.close();
+                    last_error = None;
+                    sounds.play(Cue::Start);
+                }
+                UiAction::Processing => {
+                    status = theme.status_markup(Status::Connected, "Processing");
+                    session_state = SessionState::Processing;
+                    sounds.play(Cue::Stop);
+                }
+                UiAction::SessionStarted(new_profile) => {
+                    cancelled = false;
+                    profile = new_profile;
+                    detected_language = None;
+                    live_typer = LiveTyper::new(profile.live_typing.stable_updates);
+                    // Text from a previous session that was not accepted is discarded
+                    if redictating.is_none() {
+                        review_accepted = Some(false);
+                    }
+                    // Don't keep typing text of the previous session into whatever is focused now
+                    virtual_keyboard_sender.cancel();
+                    widgets.set_translated(profile.session_opts.task == Some(Task::Translate));
+                }
+                UiAction::LanguageChanged(language) => {
+                    profile.session_opts.language = language;
+                    detected_language = None;
+                }
+                UiAction::Cancel => {
+                    info!("Cancelling the session");
+                    cancelled = true;
+                    review_accepted = Some(false);
+                    virtual_keyboard_sender.cancel();
+                    widgets.set_realtime("");
+                }
+                UiAction::AcceptReview => review_accepted = Some(true),
+                UiAction::DiscardReview => review_accepted = Some(false),
+                UiAction::ReviewTimeout(id) => {
+                    if id == review_id {
+                        review_accepted = Some(true);
+                    }
+                }
+                UiAction::SelectWord(step) => {
+                    let candidates = low_confidence_words(&review_lines, threshold);
+                    if !candidates.is_empty() {
+                        let len = candidates.len() as isize;
+                        let next = match review_selected
+                            .and_then(|x| candidates.iter().position(|c| *c == x))
+                        {
+                            Some(i) => (i as isize + step).rem_euclid(len),
+                            None if step > 0 => 0,
+                            None => len - 1,
+                        };
+                        review_selected = Some(candidates[next as usize]);
+                        review_changed = true;
+                    }
                 }
+                UiAction::CycleAlternative(step) => {
+                    if let Some(word) =
+                        review_selected.and_then(|(li, wi)| review_lines.get_mut(li)?.get_mut(wi))
+                    {
+                        let mut candidates = vec![word.word.clone()];
+                        candidates.append(&mut word.alternatives);
+                        if step > 0 {
+                            candidates.rotate_left(1);
+                        } else {
+                            candidates.rotate_right(1);
+                        }
+                        word.word = candidates.remove(0);
+                        word.alternatives = candidates;
+                        review_changed = true;
+                    }
+                }
+                UiAction::Redictate(true) => {
+                    if let (Some(selected), None) = (review_selected, redictating) {
+                        redictating = Some(selected);
+                        review_changed = true;
+                        let _ = profile_sender.send(profile.clone());
+                        let _ = connection_sender.send(ConnectionState::Connected);
+                    }
+                }
+                UiAction::Redictate(false) => {
+                    if redictating.is_some() {
+                        let _ = connection_sender.send(ConnectionState::Disconnected);
+                    }
+                }
+                UiAction::MicLevel(level) => {
+                    widgets.set_level(level);
+                    continue;
+                }
+                UiAction::CycleDisplayMode | UiAction::SetDisplayMode(_) => {
+                    let mode = match ui_action {
+                        UiAction::SetDisplayMode(mode) => mode,
+                        _ => widgets.mode.next(),
+                    };
+                    info!(?mode, "Switching display mode");
+                    widgets.set_mode(mode, &theme);
+                    window.set_child(Some(&widgets.root));
+                    place_window(&window, &overlay_opts, mode, &placement);
+                    let _ = display_mode_sender.send(mode);
+                }
+            }
+
+            if review_changed {
+                // Interacting with the review stops the automatic acceptance
+                review_id += 1;
             }
+
+            if let Some(accepted) = review_accepted.filter(|_| !review_lines.is_empty()) {
+                let lines = std::mem::take(&mut review_lines);
+                review_selected = None;
+                redictating = None;
+                review_changed = true;
+                let _ = review_sender.send(false);
+
+                if window.keyboard_mode() != gtk_layer_shell::KeyboardMode::None {
+                    window.set_keyboard_mode(gtk_layer_shell::KeyboardMode::None);
+                    // Give the compositor some time to focus the previous window again
+                    glib::timeout_future(Duration::from_millis(100)).await;
+                }
+
+                if accepted {
+                    let text = lines.iter().map(|x| words_text(x)).collect::<Vec<_>>();
+                    let text = post_process(&text, &profile.post_process);
+                    record_transcript(&history_sender, &text);
+                    output_text(
+                        profile.output,
+                        text,
+                        &virtual_keyboard_sender,
+                        &sounds,
+                        &ui_sender,
+                    )
+                    .await;
+                    for markup in review_markup(&lines, None, &theme, threshold) {
+                        widgets.push_final(markup);
+                    }
+                }
+
+                if std::mem::take(&mut hide_after_review) {
+                    window.set_visible(false);
+                    window.queue_draw();
+                    widgets.clear();
+                    review_changed = false;
+                }
+            }
+
+            if review_changed {
+                widgets.set_pending(
+                    &review_markup(&review_lines, review_selected, &theme, threshold).join("\n"),
+                );
+            }
+
+            // Low confidence words can only be corrected if the overlay receives keyboard input
+            let correcting = !low_confidence_words(&review_lines, threshold).is_empty();
+            if correcting && window.keyboard_mode() != gtk_layer_shell::KeyboardMode::Exclusive {
+                window.set_keyboard_mode(gtk_layer_shell::KeyboardMode::Exclusive);
+            }
+
+            let state = OverlayState {
+                state: session_state,
+                error: session_error.clone(),
+                profile: profile.name.clone(),
+                language: profile.session_opts.language.clone(),
+                detected_language: detected_language.clone(),
+                translate: profile.session_opts.task == Some(Task::Translate),
+                review_pending: !review_lines.is_empty(),
+                queue_position: queue_position.filter(|_| session_state == SessionState::Locking),
+            };
+            state_sender.send_if_modified(|x| {
+                let modified = *x != state;
+                *x = state;
+                modified
+            });
+
+            let mut markup = status.clone();
+            if let Some(error) = &status_error {
+                markup += &format!(
+                    " <span color='{}'>{}</span>",
+                    theme.muted,
+                    glib::markup_escape_text(error)
+                );
+            }
+            markup += &language_markup(
+                &theme,
+                profile.session_opts.language.as_deref(),
+                detected_language.as_deref(),
+            );
+            if profile.name != "default" {
+                markup += &format!(
+                    " <span color='{}'>{}</span>",
+                    theme.muted,
+                    glib::markup_escape_text(&profile.name)
+                );
+            }
+            if profile.session_opts.task == Some(Task::Translate) {
+                markup += &format!(" <span color='{}'>→ english</span>", theme.translate);
+            }
+            if redictating.is_some() {
+                markup += &format!(
+                    " <span color='{}'>release R to replace the word</span>",
+                    theme.review
+                );
+            } else if !review_lines.is_empty() {
+                markup += &format!(
+                    " <span color='{}'>{:?} to type",
+                    theme.review, profile.hotkey
+                );
+                if let Some(discard_key) = hotkeys.review_discard {
+                    markup += &format!(", {discard_key:?} to discard");
+                }
+                if correcting {
+                    markup += ", ←/→ select word, ↑/↓ alternatives, hold R to dictate again";
+                }
+                markup += "</span>";
+            }
+            widgets.set_status(&markup);
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_text_uses_ordinal_suffixes() {
+        let texts: Vec<_> = [1, 2, 3, 4, 11, 12, 13, 21, 22, 23, 101, 111]
+            .into_iter()
+            .map(|x| queue_text(x, None))
+            .collect();
+        assert_eq!(
+            texts,
+            [
+                "1st", "2nd", "3rd", "4th", "11th", "12th", "13th", "21st", "22nd", "23rd",
+                "101st", "111th"
+            ]
+        );
+    }
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        ["whisper-overlay", "overlay"]
+            .iter()
+            .chain(args)
+            .map(OsString::from)
+            .collect()
+    }
+
+    fn profiles() -> Vec<Profile> {
+        ["default", "translate"]
+            .into_iter()
+            .map(|name| Profile {
+                name: name.to_string(),
+                hotkey: Key::KEY_RIGHTCTRL,
+                connection_opts: ConnectionOpts {
+                    address: "localhost:7007".to_string(),
+                },
+                session_opts: SessionOpts::default(),
+                output: OutputSink::Type,
+                post_process: Default::default(),
+                review: ReviewOpts::default(),
+                live_typing: LiveTypingOpts::default(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn remote_changes_applies_runtime_options() {
+        let own = args(&["--hotkey", "KEY_F9", "--sounds"]);
+        let forwarded = args(&[
+            "--sounds",
+            "--hotkey",
+            "KEY_F9",
+            "--profile",
+            "translate",
+            "--language",
+            "de",
+            "--display-mode",
+            "pill",
+            "--action",
+            "toggle",
+        ]);
+        let (changes, not_applied) = remote_changes(&own, &forwarded, &profiles()).unwrap();
+        assert!(not_applied.is_empty());
+        assert!(matches!(
+            changes.as_slice(),
+            [
+                RemoteChange::Profile(1),
+                RemoteChange::Language(Some(language)),
+                RemoteChange::DisplayMode(DisplayMode::Pill),
+                RemoteChange::Request(ControlRequest::Toggle),
+            ] if language == "de"
+        ));
+    }
+
+    #[test]
+    fn remote_changes_reports_startup_options() {
+        let own = args(&["--hotkey", "KEY_F9"]);
+        let forwarded = args(&["--hotkey", "KEY_F10", "--review", "--language", "auto"]);
+        let (changes, not_applied) = remote_changes(&own, &forwarded, &profiles()).unwrap();
+        assert_eq!(not_applied, ["--hotkey", "--review"]);
+        assert!(matches!(changes.as_slice(), [RemoteChange::Language(None)]));
+    }
+
+    #[test]
+    fn remote_changes_rejects_unknown_profiles() {
+        let forwarded = args(&["--profile", "missing"]);
+        assert!(remote_changes(&args(&[]), &forwarded, &profiles()).is_err());
+    }
+
+    #[test]
+    fn queue_text_shows_eta() {
+        assert_eq!(queue_text(2, Some(5)), "2nd, ~5s");
+        assert_eq!(queue_text(1, Some(0)), "1st, ~0s");
+    }
+}
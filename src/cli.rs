@@ -1,31 +1,180 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use evdev::Key;
+use serde::Deserialize;
 use std::path::PathBuf;
 
+use crate::control::ControlRequest;
+use crate::display::DisplayMode;
+use crate::keyboard::TypingBackend;
+use crate::profile::parse_key;
+use crate::theme::ThemeName;
+use crate::waybar::StatusFormat;
+
 #[derive(Parser)]
 #[command(version, about)]
 pub struct Cli {
+    #[clap(flatten)]
+    pub log_opts: LogOpts,
+
     #[clap(subcommand)]
     pub command: Command,
 }
 
+#[derive(Debug, Args, Clone)]
+pub struct LogOpts {
+    /// The log level, or a filter in the syntax of RUST_LOG such as "info,whisper_overlay=debug".
+    /// Takes precedence over RUST_LOG. [default: info]
+    #[arg(long, global = true)]
+    pub log_level: Option<String>,
+    /// Write log messages to the systemd journal instead of stderr
+    #[arg(long, global = true)]
+    pub journald: bool,
+    /// Include transcribed text in log messages. It is redacted by default.
+    #[arg(long, global = true)]
+    pub log_transcripts: bool,
+}
+
 #[derive(Debug, Subcommand, Clone)]
 pub enum Command {
+    /// Continuously prints the status of the server and the local overlay for a status bar
+    #[command(alias = "bar-status")]
     WaybarStatus {
         #[clap(flatten)]
         connection_opts: ConnectionOpts,
+
+        /// An optional configuration file, of which the [waybar] section is used.
+        /// Defaults to $XDG_CONFIG_HOME/whisper-overlay/config.toml if it exists.
+        #[arg(short, long, default_value=None)]
+        config: Option<PathBuf>,
+
+        /// The output format, depending on the status bar
+        #[arg(long, default_value = "waybar")]
+        format: StatusFormat,
+    },
+    /// Prints the current status of the server, including its models, queue and latency
+    ServerStatus {
+        #[clap(flatten)]
+        connection_opts: ConnectionOpts,
+
+        /// An optional configuration file, of which the [waybar] section is used.
+        /// Defaults to $XDG_CONFIG_HOME/whisper-overlay/config.toml if it exists.
+        #[arg(short, long, default_value=None)]
+        config: Option<PathBuf>,
+
+        /// Print the status as json, for use in scripts
+        #[arg(long)]
+        json: bool,
+    },
+    /// Controls the running overlay, for example from click actions of a status bar
+    Ctl {
+        #[arg(value_enum)]
+        request: ControlRequest,
+    },
+    /// Creates systemd user units which start the overlay with the graphical session
+    /// or on first use of its control socket, and optionally units for the server
+    InstallService {
+        /// The directory in which the units are created. Defaults to $XDG_CONFIG_HOME/systemd/user
+        #[arg(long, default_value=None)]
+        dir: Option<PathBuf>,
+
+        /// The command which runs the server, such as "/path/to/venv/bin/python /path/to/realtime-stt-server.py".
+        /// The server is started on the first connection. No server units are created if unset.
+        #[arg(long, default_value=None)]
+        server_command: Option<String>,
+
+        /// The address on which systemd listens for connections to the server
+        #[arg(long, default_value = "127.0.0.1:7007")]
+        server_listen: String,
+
+        /// Arguments passed to the overlay, given after "--"
+        #[arg(last = true)]
+        overlay_args: Vec<String>,
     },
     Overlay {
         #[clap(flatten)]
         connection_opts: ConnectionOpts,
 
+        #[clap(flatten)]
+        session_opts: SessionOpts,
+
+        /// An optional configuration file. Defaults to $XDG_CONFIG_HOME/whisper-overlay/config.toml
+        /// if it exists. Options given on the command line take precedence.
+        #[arg(short, long, default_value=None)]
+        config: Option<PathBuf>,
+
         /// An optional stylesheet for the overlay, which replaces the internal style.
         #[arg(short, short, long, default_value=None)]
         style: Option<PathBuf>,
 
+        /// The color theme of the overlay. [default: dark]
+        #[arg(long, default_value=None)]
+        theme: Option<ThemeName>,
+
+        /// The layout of the overlay. [default: full]
+        #[arg(long, default_value=None)]
+        display_mode: Option<DisplayMode>,
+
+        /// The profile used by requests which start a session without its hotkey,
+        /// such as `--action start` or `ctl start`. [default: default]
+        #[arg(long, default_value=None)]
+        profile: Option<String>,
+
+        /// Starts, stops or toggles recording. This is mostly useful while the overlay
+        /// is already running, as it applies the arguments of further invocations.
+        #[arg(long, default_value=None)]
+        action: Option<SessionAction>,
+
         /// Specifies the hotkey to activate voice input. You can use any
         /// key or button name from [evdev::Key](https://docs.rs/evdev/latest/evdev/struct.Key.html)
-        #[arg(long, default_value="KEY_RIGHTCTRL")]
-        hotkey: String,
+        #[arg(long, default_value="KEY_RIGHTCTRL", value_parser = parse_key)]
+        hotkey: Key,
+
+        /// The languages to cycle through with the language hotkey, separated by commas.
+        /// Use "auto" to let the model detect the language.
+        #[arg(long, value_delimiter = ',')]
+        languages: Vec<String>,
+
+        /// An optional hotkey which switches to the next configured language
+        #[arg(long, default_value=None, value_parser = parse_key)]
+        language_hotkey: Option<Key>,
+
+        /// An optional modifier key. Holding it while pressing the hotkey
+        /// starts a session that translates your speech to english.
+        #[arg(long, default_value=None, value_parser = parse_key)]
+        translate_modifier: Option<Key>,
+
+        /// An optional hotkey which switches the overlay to the next display mode
+        #[arg(long, default_value=None, value_parser = parse_key)]
+        display_mode_hotkey: Option<Key>,
+
+        /// The method used to type the transcribed text. If the selected backend is unavailable,
+        /// the remaining backends are tried in order. [default: auto]
+        #[arg(long, default_value=None)]
+        typing_backend: Option<TypingBackend>,
+
+        /// The delay between two key presses in milliseconds. Increase this if
+        /// some applications drop or reorder typed characters. [default: 0]
+        #[arg(long, default_value=None)]
+        key_delay_ms: Option<u64>,
+
+        /// Type words while you are still speaking, as soon as they no longer
+        /// change between realtime updates. Corrections from the final result
+        /// are applied by erasing and retyping the differing part.
+        #[arg(long)]
+        live_typing: bool,
+
+        /// Show the final text in the overlay for review instead of outputting it directly.
+        /// Press the hotkey again to accept it, or escape to discard it.
+        #[arg(long)]
+        review: bool,
+
+        /// Show desktop notifications for errors and when the server is busy
+        #[arg(long)]
+        notifications: bool,
+
+        /// Play short sounds when recording starts and stops, when text is typed and on errors
+        #[arg(long)]
+        sounds: bool,
     },
 }
 
@@ -35,3 +184,95 @@ pub struct ConnectionOpts {
     #[clap(short, long, default_value="localhost:7007")]
     pub address: String,
 }
+
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Task {
+    /// Transcribe speech in the spoken language
+    Transcribe,
+    /// Translate speech to english
+    Translate,
+}
+
+/// Starts or stops recording, see `--action`
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum SessionAction {
+    /// Start recording
+    Start,
+    /// Stop recording
+    Stop,
+    /// Start or stop recording
+    Toggle,
+    /// Stop recording and discard the text
+    Cancel,
+}
+
+impl From<SessionAction> for ControlRequest {
+    fn from(action: SessionAction) -> Self {
+        match action {
+            SessionAction::Start => ControlRequest::Start,
+            SessionAction::Stop => ControlRequest::Stop,
+            SessionAction::Toggle => ControlRequest::Toggle,
+            SessionAction::Cancel => ControlRequest::Cancel,
+        }
+    }
+}
+
+impl Task {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Task::Transcribe => "transcribe",
+            Task::Translate => "translate",
+        }
+    }
+}
+
+#[derive(Debug, Args, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct SessionOpts {
+    /// The spoken language to request from the server (e.g. "en").
+    /// If unset, the server's default language is used.
+    #[arg(long)]
+    pub language: Option<String>,
+
+    /// An initial prompt for the model, which helps it to pick up
+    /// the correct spelling and style of your text
+    #[arg(long)]
+    pub initial_prompt: Option<String>,
+
+    /// A word or name that the model should recognize, such as project
+    /// names or jargon. Can be given multiple times.
+    #[arg(long = "hotword", value_name = "HOTWORD")]
+    pub hotwords: Vec<String>,
+
+    /// Whether to transcribe speech or to translate it to english.
+    /// If unset, speech is transcribed.
+    #[arg(long)]
+    pub task: Option<Task>,
+}
+
+impl SessionOpts {
+    /// Fills all options that were not set in `self` from the given fallback.
+    /// A language of "auto" takes precedence over the fallback, but is not
+    /// requested from the server.
+    pub fn or(self, fallback: &SessionOpts) -> SessionOpts {
+        SessionOpts {
+            language: requested_language(self.language.or_else(|| fallback.language.clone())),
+            initial_prompt: self
+                .initial_prompt
+                .or_else(|| fallback.initial_prompt.clone()),
+            hotwords: if self.hotwords.is_empty() {
+                fallback.hotwords.clone()
+            } else {
+                self.hotwords
+            },
+            task: self.task.or(fallback.task),
+        }
+    }
+}
+
+/// Returns the language that is requested from the server, where "auto"
+/// leaves the language unset so that the server's default is used.
+pub fn requested_language(language: Option<String>) -> Option<String> {
+    language.filter(|x| x != "auto")
+}
@@ -0,0 +1,117 @@
+use color_eyre::eyre::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::cli::SessionOpts;
+use crate::keyboard::{TypingBackend, TypingOpts};
+use crate::live::{LiveTypingOpts, LiveTypingOverrides};
+use crate::metrics::MetricsOpts;
+use crate::notification::NotificationOpts;
+use crate::output::{
+    OutputSink, PostProcessOpts, PostProcessOverrides, ReviewOpts, ReviewOverrides,
+};
+use crate::overlay::OverlayOpts;
+use crate::rules::AppRule;
+use crate::sound::SoundOpts;
+use crate::waybar::WaybarOpts;
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// Global defaults for each transcription session
+    pub session: SessionOpts,
+    /// The name shown to other users of the server while this client holds
+    /// or waits for the model lock. Nothing is sent unless this is set.
+    pub client_name: Option<String>,
+    /// The languages to cycle through with the language hotkey
+    pub languages: Vec<String>,
+    /// The hotkey which switches to the next configured language
+    pub language_hotkey: Option<String>,
+    /// The modifier key which enables translation while held
+    pub translate_modifier: Option<String>,
+    /// The key which switches the overlay to the next display mode
+    pub display_mode_hotkey: Option<String>,
+    /// An additional key which accepts text pending review
+    pub review_accept_key: Option<String>,
+    /// The key which discards text pending review (default: KEY_ESC)
+    pub review_discard_key: Option<String>,
+    /// Where the transcribed text should go
+    pub output: OutputSink,
+    /// The method used to type the transcribed text
+    pub typing_backend: TypingBackend,
+    /// Controls the typing speed and when text is pasted instead
+    pub typing: TypingOpts,
+    /// How the transcribed text is processed before it is output
+    pub post_process: PostProcessOpts,
+    /// Whether the transcribed text must be accepted before it is output
+    pub review: ReviewOpts,
+    /// Whether and how words are typed while speaking
+    pub live_typing: LiveTypingOpts,
+    /// Additional named profiles, each bound to its own hotkey
+    pub profiles: BTreeMap<String, ProfileConfig>,
+    /// Appearance and behavior of the overlay
+    pub overlay: OverlayOpts,
+    /// Desktop notifications for errors and long waits
+    pub notifications: NotificationOpts,
+    /// Sound cues for session events
+    pub sounds: SoundOpts,
+    /// Texts and reconnect behavior of the status bar module
+    pub waybar: WaybarOpts,
+    /// The local metrics endpoint of the overlay
+    pub metrics: MetricsOpts,
+    /// Rules that change the output depending on the focused application.
+    /// The first matching rule is applied.
+    pub rules: Vec<AppRule>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ProfileConfig {
+    /// The hotkey which starts a session using this profile
+    pub hotkey: String,
+    /// The address of the whisper streaming instance (host:port)
+    pub address: Option<String>,
+    /// Session options, unset options are taken from the global session options
+    #[serde(default)]
+    pub session: SessionOpts,
+    pub output: Option<OutputSink>,
+    #[serde(default)]
+    pub post_process: PostProcessOverrides,
+    #[serde(default)]
+    pub review: ReviewOverrides,
+    #[serde(default)]
+    pub live_typing: LiveTypingOverrides,
+}
+
+fn default_config_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("whisper-overlay").join("config.toml"))
+}
+
+/// Loads the configuration from the given file. If no file is given,
+/// the default location is tried and an empty configuration is returned
+/// if it doesn't exist.
+pub fn load_config(path: Option<&Path>) -> Result<Config> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => match default_config_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Config::default()),
+        },
+    };
+
+    let content = std::fs::read_to_string(&path)
+        .wrap_err_with(|| format!("Failed to read config file {}", path.display()))?;
+    let config: Config = toml::from_str(&content)
+        .wrap_err_with(|| format!("Failed to parse config file {}", path.display()))?;
+    for (i, rule) in config.rules.iter().enumerate() {
+        rule.validate().wrap_err_with(|| {
+            format!("Invalid rule {} in config file {}", i + 1, path.display())
+        })?;
+    }
+
+    Ok(config)
+}
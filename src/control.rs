@@ -0,0 +1,233 @@
+use clap::ValueEnum;
+use color_eyre::eyre::{bail, Context, Result};
+use gtk::{gio, glib, prelude::*};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::PathBuf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, watch};
+use tracing::warn;
+
+use crate::util::{recv_message, send_message};
+
+/// The state of the current session of the overlay
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SessionState {
+    #[default]
+    Idle,
+    Connecting,
+    /// Waiting for the model lock, because the server is busy
+    Locking,
+    Recording,
+    /// Recording has stopped and the server is processing the final result
+    Processing,
+    Error,
+}
+
+impl SessionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionState::Idle => "idle",
+            SessionState::Connecting => "connecting",
+            SessionState::Locking => "locking",
+            SessionState::Recording => "recording",
+            SessionState::Processing => "processing",
+            SessionState::Error => "error",
+        }
+    }
+}
+
+/// The state of this overlay instance, which is published on the control socket
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OverlayState {
+    pub state: SessionState,
+    /// The reason of the last error, if the state is error
+    pub error: Option<String>,
+    pub profile: String,
+    /// The requested language, or `None` for auto-detection
+    pub language: Option<String>,
+    /// The language detected by the server, if auto-detection is used
+    pub detected_language: Option<String>,
+    pub translate: bool,
+    pub review_pending: bool,
+    /// The position in the queue for the model lock, while waiting for it
+    #[serde(default)]
+    pub queue_position: Option<u32>,
+}
+
+/// A request sent to the control socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ControlRequest {
+    /// Print the current state of the overlay
+    State,
+    /// Print the state of the overlay and every change, until interrupted
+    Subscribe,
+    /// Print the most recent transcriptions, newest first
+    History,
+    /// Start recording with the last used profile
+    Start,
+    /// Stop recording
+    Stop,
+    /// Start or stop recording
+    Toggle,
+    /// Stop recording and discard the text
+    Cancel,
+    /// Switch to the next configured language
+    CycleLanguage,
+    /// Switch the overlay to the next display mode
+    CycleDisplayMode,
+}
+
+/// The path of the control socket of the overlay
+pub fn socket_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("whisper-overlay.sock")
+}
+
+/// Serves the control socket, which allows other processes to follow the state of the
+/// overlay and to control it. Requests that change the state are forwarded to `commands`.
+/// If `activated` is given, the socket passed by systemd is served instead of binding one.
+pub async fn serve(
+    activated: Option<std::os::unix::net::UnixListener>,
+    state: watch::Receiver<OverlayState>,
+    history: watch::Receiver<Vec<String>>,
+    commands: mpsc::Sender<ControlRequest>,
+) -> Result<()> {
+    let listener = match activated {
+        Some(listener) => {
+            listener.set_nonblocking(true)?;
+            UnixListener::from_std(listener)?
+        }
+        None => {
+            let path = socket_path();
+            if UnixStream::connect(&path).await.is_ok() {
+                bail!("Another overlay is already serving {}", path.display());
+            }
+            // A socket left behind by a previous instance prevents binding
+            let _ = std::fs::remove_file(&path);
+            UnixListener::bind(&path)
+                .wrap_err_with(|| format!("Could not bind control socket {}", path.display()))?
+        }
+    };
+
+    loop {
+        let (socket, _) = listener.accept().await?;
+        let state = state.clone();
+        let history = history.clone();
+        let commands = commands.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(socket, state, history, commands).await {
+                warn!("Control socket client failed: {e:#}");
+            }
+        });
+    }
+}
+
+async fn handle_client(
+    mut socket: UnixStream,
+    mut state: watch::Receiver<OverlayState>,
+    history: watch::Receiver<Vec<String>>,
+    commands: mpsc::Sender<ControlRequest>,
+) -> Result<()> {
+    let message = recv_message(&mut socket).await?;
+    let request = match serde_json::from_value(message["command"].clone()) {
+        Ok(request) => request,
+        Err(e) => {
+            let error = json!({"error": format!("invalid request {message}: {e}")});
+            return send_message(&mut socket, error).await;
+        }
+    };
+
+    match request {
+        ControlRequest::State => {
+            let current = serde_json::to_value(&*state.borrow())?;
+            send_message(&mut socket, current).await
+        }
+        // Sends the current state and then every change, until the client disconnects
+        ControlRequest::Subscribe => loop {
+            let current = serde_json::to_value(&*state.borrow_and_update())?;
+            send_message(&mut socket, current).await?;
+            state.changed().await?;
+        },
+        ControlRequest::History => {
+            let history = json!({"history": *history.borrow()});
+            send_message(&mut socket, history).await
+        }
+        _ => {
+            let reply = match commands.send(request).await {
+                Ok(()) => json!({"ok": true}),
+                Err(_) => json!({"error": "the overlay is shutting down"}),
+            };
+            send_message(&mut socket, reply).await
+        }
+    }
+}
+
+/// Connects to the control socket of a running overlay and sends the given request.
+pub async fn connect(request: ControlRequest) -> Result<UnixStream> {
+    let mut socket = UnixStream::connect(socket_path())
+        .await
+        .wrap_err("Could not connect to the overlay, is it running?")?;
+    send_message(&mut socket, json!({"command": request})).await?;
+    Ok(socket)
+}
+
+/// Whether an overlay is running in this session, determined by its name on the session bus.
+/// Unlike connecting to the control socket, this doesn't start the overlay if it is socket
+/// activated. If the session bus is unavailable, the overlay is assumed to be running.
+pub fn overlay_running() -> bool {
+    let Ok(connection) = gio::bus_get_sync(gio::BusType::Session, gio::Cancellable::NONE) else {
+        return true;
+    };
+    let reply = connection.call_sync(
+        Some("org.freedesktop.DBus"),
+        "/org/freedesktop/DBus",
+        "org.freedesktop.DBus",
+        "NameHasOwner",
+        Some(&(crate::app::APP_ID,).to_variant()),
+        glib::VariantTy::new("(b)").ok(),
+        gio::DBusCallFlags::NONE,
+        1000,
+        gio::Cancellable::NONE,
+    );
+    reply
+        .ok()
+        .and_then(|x| x.get::<(bool,)>())
+        .map_or(true, |(running,)| running)
+}
+
+/// Connects to the control socket of a running overlay and subscribes to its state.
+/// Each state is sent as a separate message.
+pub async fn subscribe() -> Result<UnixStream> {
+    connect(ControlRequest::Subscribe).await
+}
+
+/// Sends a request to the running overlay and prints the reply.
+pub async fn main_ctl(request: ControlRequest) -> Result<()> {
+    let mut socket = connect(request).await?;
+    loop {
+        let reply = recv_message(&mut socket).await?;
+        if let Some(error) = reply.get("error") {
+            bail!("{}", error.as_str().unwrap_or_default());
+        }
+
+        match request {
+            ControlRequest::State => println!("{}", serde_json::to_string_pretty(&reply)?),
+            ControlRequest::Subscribe => {
+                println!("{reply}");
+                continue;
+            }
+            ControlRequest::History => {
+                for text in reply["history"].as_array().into_iter().flatten() {
+                    println!("{}", text.as_str().unwrap_or_default());
+                }
+            }
+            _ => {}
+        }
+        return Ok(());
+    }
+}
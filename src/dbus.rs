@@ -0,0 +1,113 @@
+use color_eyre::eyre::{eyre, Result};
+use gtk::{gio, glib, prelude::*};
+use std::collections::HashMap;
+use tokio::sync::{mpsc, watch};
+use tracing::warn;
+
+use crate::control::{ControlRequest, OverlayState};
+
+const INTERFACE_NAME: &str = "org.oddlama.WhisperOverlay";
+const INTERFACE_XML: &str = r#"
+<node>
+  <interface name="org.oddlama.WhisperOverlay">
+    <method name="Start"/>
+    <method name="Stop"/>
+    <method name="Toggle"/>
+    <method name="Cancel"/>
+    <property name="State" type="s" access="read"/>
+    <property name="LastResult" type="s" access="read"/>
+  </interface>
+</node>
+"#;
+
+fn state_variant(state: &OverlayState) -> glib::Variant {
+    state.state.as_str().to_variant()
+}
+
+fn last_result_variant(history: &[String]) -> glib::Variant {
+    history.first().cloned().unwrap_or_default().to_variant()
+}
+
+/// Exports the control interface on the D-Bus connection of the application, at its
+/// object path. Methods are forwarded to `commands`, and changes of the properties
+/// are announced with the PropertiesChanged signal.
+pub fn register(
+    app: &gtk::Application,
+    mut state: watch::Receiver<OverlayState>,
+    mut history: watch::Receiver<Vec<String>>,
+    commands: mpsc::Sender<ControlRequest>,
+) -> Result<()> {
+    let connection = app
+        .dbus_connection()
+        .ok_or_else(|| eyre!("the application is not connected to the session bus"))?;
+    let path = app
+        .dbus_object_path()
+        .ok_or_else(|| eyre!("the application has no object path"))?
+        .to_string();
+
+    let node = gio::DBusNodeInfo::for_xml(INTERFACE_XML)?;
+    let interface = node
+        .lookup_interface(INTERFACE_NAME)
+        .ok_or_else(|| eyre!("missing interface {INTERFACE_NAME}"))?;
+
+    let properties_state = state.clone();
+    let properties_history = history.clone();
+    connection
+        .register_object(&path, &interface)
+        .method_call(move |_, _, _, _, method, _, invocation| {
+            let request = match method {
+                "Start" => ControlRequest::Start,
+                "Stop" => ControlRequest::Stop,
+                "Toggle" => ControlRequest::Toggle,
+                "Cancel" => ControlRequest::Cancel,
+                _ => {
+                    invocation.return_error(gio::IOErrorEnum::NotSupported, "unknown method");
+                    return;
+                }
+            };
+            match commands.try_send(request) {
+                Ok(()) => invocation.return_value(None),
+                Err(_) => invocation.return_error(gio::IOErrorEnum::Busy, "the overlay is busy"),
+            }
+        })
+        .get_property(move |_, _, _, _, property| match property {
+            "LastResult" => last_result_variant(&properties_history.borrow()),
+            _ => state_variant(&properties_state.borrow()),
+        })
+        .build()?;
+
+    // Announce property changes until the overlay stops
+    glib::spawn_future_local(async move {
+        let mut last_state = state_variant(&state.borrow_and_update());
+        loop {
+            let changed = tokio::select! {
+                Ok(()) = state.changed() => {
+                    let value = state_variant(&state.borrow_and_update());
+                    if value == last_state {
+                        continue;
+                    }
+                    last_state = value.clone();
+                    ("State", value)
+                }
+                Ok(()) = history.changed() => {
+                    ("LastResult", last_result_variant(&history.borrow_and_update()))
+                }
+                else => break,
+            };
+
+            let changed = HashMap::from([(changed.0.to_string(), changed.1)]);
+            let parameters = (INTERFACE_NAME, changed, Vec::<String>::new()).to_variant();
+            if let Err(e) = connection.emit_signal(
+                None,
+                &path,
+                "org.freedesktop.DBus.Properties",
+                "PropertiesChanged",
+                Some(&parameters),
+            ) {
+                warn!("Could not announce changed D-Bus property: {e}");
+            }
+        }
+    });
+
+    Ok(())
+}
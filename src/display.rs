@@ -0,0 +1,391 @@
+use clap::ValueEnum;
+use gtk::prelude::*;
+use gtk::{glib, Label};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::overlay::OverlayOpts;
+use crate::theme::Theme;
+
+/// The layout of the overlay
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DisplayMode {
+    /// Large live text with the status line below
+    #[default]
+    Full,
+    /// A small pill showing only the status and microphone level
+    Pill,
+    /// A single line showing the most recent words
+    Ticker,
+    /// A single line next to the mouse cursor (hyprland only, others behave like ticker)
+    Cursor,
+}
+
+impl DisplayMode {
+    /// The mode that follows this one when cycling through modes
+    pub fn next(self) -> Self {
+        match self {
+            DisplayMode::Full => DisplayMode::Pill,
+            DisplayMode::Pill => DisplayMode::Ticker,
+            DisplayMode::Ticker => DisplayMode::Cursor,
+            DisplayMode::Cursor => DisplayMode::Full,
+        }
+    }
+
+    fn css_class(self) -> &'static str {
+        match self {
+            DisplayMode::Full => "mode-full",
+            DisplayMode::Pill => "mode-pill",
+            DisplayMode::Ticker => "mode-ticker",
+            DisplayMode::Cursor => "mode-cursor",
+        }
+    }
+}
+
+/// The text shown in the overlay, independent of the display mode
+#[derive(Debug, Default)]
+struct Content {
+    status: String,
+    /// Finalized lines and the time at which they were finalized
+    history: VecDeque<(Instant, String)>,
+    /// Finalized lines which are pending review
+    pending: String,
+    /// The realtime result of the current utterance
+    realtime: String,
+    /// Set if the content has changed since it was last rendered
+    dirty: bool,
+}
+
+/// The labels of one display mode, which render the content on each frame
+struct Renderer {
+    history: Option<Label>,
+    pending: Option<Label>,
+    realtime: Option<Label>,
+    /// The single line in ticker and cursor mode
+    line: Option<Label>,
+    status: Option<Label>,
+    scroll: Option<gtk::ScrolledWindow>,
+    max_lines: usize,
+    history_timeout: Duration,
+    fade: Duration,
+}
+
+fn label() -> gtk::builders::LabelBuilder {
+    Label::builder()
+        .halign(gtk::Align::Start)
+        .can_target(false)
+        .can_focus(false)
+        .focus_on_click(false)
+}
+
+fn set_text(label: &Option<Label>, markup: &str) {
+    if let Some(label) = label {
+        label.set_markup(markup);
+        label.set_visible(!markup.is_empty());
+    }
+}
+
+impl Renderer {
+    /// Renders a finalized line, which fades out after the history timeout
+    fn history_markup(&self, age: Duration, markup: &str) -> String {
+        let Some(fading) = age.checked_sub(self.history_timeout) else {
+            return markup.to_string();
+        };
+
+        let alpha = 1.0 - fading.as_secs_f64() / self.fade.as_secs_f64().max(0.001);
+        let alpha = ((alpha * 100.0) as u32).clamp(1, 100);
+        // Each line is wrapped on its own, so single lines can be taken from the result
+        markup
+            .lines()
+            .map(|line| format!("<span alpha=\"{alpha}%\">{line}</span>"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn render(&self, content: &mut Content) {
+        let now = Instant::now();
+        let lifetime = self.history_timeout + self.fade;
+        let count = content.history.len();
+        content
+            .history
+            .retain(|(time, _)| now.duration_since(*time) < lifetime);
+        let fading = content
+            .history
+            .iter()
+            .any(|(time, _)| now.duration_since(*time) >= self.history_timeout);
+        if !std::mem::take(&mut content.dirty) && !fading && count == content.history.len() {
+            return;
+        }
+
+        let history: Vec<String> = content
+            .history
+            .iter()
+            .map(|(time, markup)| self.history_markup(now.duration_since(*time), markup))
+            .collect();
+
+        if let Some(status) = &self.status {
+            status.set_markup(&content.status);
+        }
+
+        if let Some(line) = &self.line {
+            // The most recent line, or the status if nothing was said yet
+            let markup = [&content.realtime, &content.pending]
+                .into_iter()
+                .chain(history.last())
+                .find_map(|x| x.lines().last().filter(|x| !x.is_empty()))
+                .unwrap_or(content.status.as_str());
+            line.set_markup(markup);
+        }
+
+        set_text(&self.history, &history.join("\n"));
+        set_text(&self.pending, &content.pending);
+        if let Some(realtime) = &self.realtime {
+            realtime.set_markup(&content.realtime);
+            // Keep the height of one line while nothing is shown, so the overlay doesn't jump
+            realtime.set_visible(
+                !content.realtime.is_empty() || (history.is_empty() && content.pending.is_empty()),
+            );
+        }
+
+        if let (Some(scroll), Some(realtime)) = (&self.scroll, &self.realtime) {
+            let max_height = if self.max_lines == 0 {
+                -1
+            } else {
+                let (_, line_height) = realtime.create_pango_layout(Some("Ag")).pixel_size();
+                self.max_lines as i32 * line_height
+            };
+            scroll.set_max_content_height(max_height);
+        }
+    }
+}
+
+/// The widget tree of the overlay for one display mode. The content is shared
+/// between modes, so it is carried over when switching to another mode.
+pub struct OverlayWidgets {
+    pub mode: DisplayMode,
+    pub root: gtk::Box,
+    content: Rc<RefCell<Content>>,
+    level: Option<gtk::LevelBar>,
+    translated: bool,
+    max_lines: usize,
+    history_timeout: Duration,
+    fade: Duration,
+}
+
+impl OverlayWidgets {
+    pub fn new(mode: DisplayMode, theme: &Theme, opts: &OverlayOpts) -> Self {
+        Self::build(
+            mode,
+            theme,
+            Rc::default(),
+            opts.max_lines,
+            Duration::from_millis(opts.history_timeout_ms),
+            Duration::from_millis(opts.fade_ms),
+        )
+    }
+
+    fn build(
+        mode: DisplayMode,
+        theme: &Theme,
+        content: Rc<RefCell<Content>>,
+        max_lines: usize,
+        history_timeout: Duration,
+        fade: Duration,
+    ) -> Self {
+        let orientation = match mode {
+            DisplayMode::Full => gtk::Orientation::Vertical,
+            _ => gtk::Orientation::Horizontal,
+        };
+        let root = gtk::Box::builder()
+            .orientation(orientation)
+            .spacing(5)
+            .can_target(false)
+            .can_focus(false)
+            .focus_on_click(false)
+            .build();
+        root.add_css_class("main-box");
+        root.add_css_class(theme.css_class());
+        root.add_css_class(mode.css_class());
+
+        let text = |class: &str| {
+            let label = label()
+                .wrap(true)
+                .wrap_mode(gdk::pango::WrapMode::WordChar)
+                .justify(gtk::Justification::Left)
+                .build();
+            label.add_css_class("live-text");
+            label.add_css_class(class);
+            label
+        };
+
+        let mut renderer = Renderer {
+            history: None,
+            pending: None,
+            realtime: None,
+            line: None,
+            status: None,
+            scroll: None,
+            max_lines,
+            history_timeout,
+            fade,
+        };
+
+        match mode {
+            DisplayMode::Full => {
+                let text_box = gtk::Box::builder()
+                    .orientation(gtk::Orientation::Vertical)
+                    .can_target(false)
+                    .build();
+                let history = text("final-text");
+                let pending = text("pending-text");
+                let realtime = text("realtime-text");
+                text_box.append(&history);
+                text_box.append(&pending);
+                text_box.append(&realtime);
+
+                // Only the most recent lines are visible, the scrollbar is hidden
+                let scroll = gtk::ScrolledWindow::builder()
+                    .hscrollbar_policy(gtk::PolicyType::Never)
+                    .vscrollbar_policy(gtk::PolicyType::External)
+                    .propagate_natural_height(true)
+                    .propagate_natural_width(true)
+                    .can_target(false)
+                    .child(&text_box)
+                    .build();
+                scroll.vadjustment().connect_changed(|adjustment| {
+                    adjustment.set_value(adjustment.upper() - adjustment.page_size());
+                });
+                root.append(&scroll);
+
+                renderer.history = Some(history);
+                renderer.pending = Some(pending);
+                renderer.realtime = Some(realtime);
+                renderer.scroll = Some(scroll);
+            }
+            DisplayMode::Pill => {}
+            DisplayMode::Ticker | DisplayMode::Cursor => {
+                // Only the end of the line is shown, which contains the most recent words
+                let line = label()
+                    .single_line_mode(true)
+                    .ellipsize(gdk::pango::EllipsizeMode::Start)
+                    .build();
+                if mode == DisplayMode::Ticker {
+                    line.set_hexpand(true);
+                } else {
+                    line.set_max_width_chars(40);
+                }
+                line.add_css_class("live-text");
+                root.append(&line);
+                renderer.line = Some(line);
+            }
+        }
+
+        if matches!(mode, DisplayMode::Full | DisplayMode::Pill) {
+            let status = label().valign(gtk::Align::Center).build();
+            status.add_css_class("connection-status");
+            root.append(&status);
+            renderer.status = Some(status);
+        }
+
+        let level = (mode == DisplayMode::Pill).then(|| {
+            let level = gtk::LevelBar::builder()
+                .min_value(0.0)
+                .max_value(1.0)
+                .width_request(80)
+                .valign(gtk::Align::Center)
+                .can_target(false)
+                .build();
+            level.add_css_class("mic-level");
+            root.append(&level);
+            level
+        });
+
+        content.borrow_mut().dirty = true;
+        root.add_tick_callback(glib::clone!(@strong content => move |widget, _| {
+            renderer.render(&mut content.borrow_mut());
+            widget.queue_draw();
+            glib::ControlFlow::Continue
+        }));
+
+        Self {
+            mode,
+            root,
+            content,
+            level,
+            translated: false,
+            max_lines,
+            history_timeout,
+            fade,
+        }
+    }
+
+    /// Replaces the widget tree by the one of the given mode.
+    /// The new root widget must be set as the child of the window afterwards.
+    pub fn set_mode(&mut self, mode: DisplayMode, theme: &Theme) {
+        let translated = self.translated;
+        *self = Self::build(
+            mode,
+            theme,
+            self.content.clone(),
+            self.max_lines,
+            self.history_timeout,
+            self.fade,
+        );
+        self.set_translated(translated);
+    }
+
+    pub fn set_status(&self, markup: &str) {
+        let mut content = self.content.borrow_mut();
+        content.status = markup.to_string();
+        content.dirty = true;
+    }
+
+    /// Adds a finalized line, which replaces the realtime text
+    pub fn push_final(&self, markup: String) {
+        let mut content = self.content.borrow_mut();
+        content.history.push_back((Instant::now(), markup));
+        content.realtime.clear();
+        content.dirty = true;
+    }
+
+    pub fn set_pending(&self, markup: &str) {
+        let mut content = self.content.borrow_mut();
+        content.pending = markup.to_string();
+        content.dirty = true;
+    }
+
+    pub fn set_realtime(&self, markup: &str) {
+        let mut content = self.content.borrow_mut();
+        content.realtime = markup.to_string();
+        content.dirty = true;
+    }
+
+    /// Removes all text
+    pub fn clear(&self) {
+        let mut content = self.content.borrow_mut();
+        content.history.clear();
+        content.pending.clear();
+        content.realtime.clear();
+        content.dirty = true;
+    }
+
+    pub fn set_translated(&mut self, translated: bool) {
+        self.translated = translated;
+        if translated {
+            self.root.add_css_class("translated");
+        } else {
+            self.root.remove_css_class("translated");
+        }
+    }
+
+    /// Updates the microphone level, ranging from 0 to 1
+    pub fn set_level(&self, level: f64) {
+        if let Some(bar) = &self.level {
+            bar.set_value(level.clamp(0.0, 1.0));
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use color_eyre::eyre::{bail, eyre, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use wayland_client::backend::ObjectId;
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::wl_registry::{self, WlRegistry};
+use wayland_client::{event_created_child, Connection, Dispatch, Proxy, QueueHandle};
+use wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_handle_v1::{
+    self, ZwlrForeignToplevelHandleV1,
+};
+use wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_manager_v1::{
+    self, ZwlrForeignToplevelManagerV1,
+};
+
+const SWAY_IPC_MAGIC: &[u8] = b"i3-ipc";
+const SWAY_IPC_GET_OUTPUTS: u32 = 3;
+const SWAY_IPC_GET_TREE: u32 = 4;
+
+/// Requests to the compositor are given up after this time, so a hung
+/// compositor doesn't block starting a session
+const COMPOSITOR_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The application that currently has keyboard focus
+#[derive(Debug, Clone)]
+pub struct FocusedApp {
+    /// The wayland app id, or the window class for X11 windows
+    pub app_id: String,
+    pub title: String,
+}
+
+async fn with_timeout<T>(request: impl Future<Output = Result<T>>) -> Result<T> {
+    tokio::time::timeout(COMPOSITOR_TIMEOUT, request)
+        .await
+        .map_err(|_| eyre!("The compositor did not respond in time"))?
+}
+
+/// Determines the focused application by asking the compositor. Sway and hyprland
+/// are supported, as well as any compositor implementing the wlr-foreign-toplevel-management
+/// protocol. For any other compositor this returns `None`.
+pub async fn focused_app() -> Result<Option<FocusedApp>> {
+    if let Some(path) = std::env::var_os("SWAYSOCK") {
+        return sway_focused_app(Path::new(&path)).await;
+    }
+
+    if let Some(signature) = std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE") {
+        return hyprland_focused_app(&signature).await;
+    }
+
+    with_timeout(async { tokio::task::spawn_blocking(toplevel_focused_app).await? }).await
+}
+
+/// Determines the name of the output (e.g. DP-1) that should show the overlay.
+/// If `follow_cursor` is set, the output containing the mouse cursor is returned
+/// where supported, otherwise the focused output is used. Currently sway and
+/// hyprland are supported, for any other compositor this returns `None`.
+pub async fn focused_output(follow_cursor: bool) -> Result<Option<String>> {
+    if let Some(path) = std::env::var_os("SWAYSOCK") {
+        // Sway doesn't expose the cursor position, so we always use the focused output
+        let outputs = sway_request(Path::new(&path), SWAY_IPC_GET_OUTPUTS).await?;
+        return Ok(outputs
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|x| x["focused"].as_bool() == Some(true))
+            .and_then(|x| x["name"].as_str())
+            .map(str::to_string));
+    }
+
+    if let Some(signature) = std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE") {
+        let monitors = hyprland_request(&signature, "j/monitors").await?;
+        let cursor = if follow_cursor {
+            Some(hyprland_request(&signature, "j/cursorpos").await?)
+        } else {
+            None
+        };
+
+        let contains_cursor = |monitor: &Value| {
+            let Some(cursor) = &cursor else {
+                return monitor["focused"].as_bool() == Some(true);
+            };
+            let get = |value: &Value, key: &str| value[key].as_f64().unwrap_or_default();
+            let scale = monitor["scale"]
+                .as_f64()
+                .filter(|x| *x > 0.0)
+                .unwrap_or(1.0);
+            let (x, y) = (get(cursor, "x"), get(cursor, "y"));
+            let (left, top) = (get(monitor, "x"), get(monitor, "y"));
+            let width = get(monitor, "width") / scale;
+            let height = get(monitor, "height") / scale;
+            x >= left && x < left + width && y >= top && y < top + height
+        };
+
+        return Ok(monitors
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|x| contains_cursor(x))
+            .and_then(|x| x["name"].as_str())
+            .map(str::to_string));
+    }
+
+    Ok(None)
+}
+
+/// Determines the output containing the mouse cursor and the cursor position
+/// relative to that output in logical pixels. Only hyprland exposes the cursor
+/// position, for any other compositor this returns `None`.
+pub async fn cursor_position() -> Result<Option<(String, i32, i32)>> {
+    let Some(signature) = std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE") else {
+        return Ok(None);
+    };
+
+    let monitors = hyprland_request(&signature, "j/monitors").await?;
+    let cursor = hyprland_request(&signature, "j/cursorpos").await?;
+    let get = |value: &Value, key: &str| value[key].as_f64().unwrap_or_default();
+    let (x, y) = (get(&cursor, "x"), get(&cursor, "y"));
+
+    Ok(monitors
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find_map(|monitor| {
+            let scale = monitor["scale"]
+                .as_f64()
+                .filter(|x| *x > 0.0)
+                .unwrap_or(1.0);
+            let (left, top) = (get(monitor, "x"), get(monitor, "y"));
+            let width = get(monitor, "width") / scale;
+            let height = get(monitor, "height") / scale;
+            if x < left || x >= left + width || y < top || y >= top + height {
+                return None;
+            }
+
+            let name = monitor["name"].as_str()?.to_string();
+            Some((name, (x - left) as i32, (y - top) as i32))
+        }))
+}
+
+fn find_focused_node(node: &Value) -> Option<&Value> {
+    if node["focused"].as_bool() == Some(true) {
+        return Some(node);
+    }
+
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node[*key].as_array())
+        .flatten()
+        .find_map(find_focused_node)
+}
+
+async fn sway_request(path: &Path, message_type: u32) -> Result<Value> {
+    with_timeout(sway_request_inner(path, message_type)).await
+}
+
+async fn sway_request_inner(path: &Path, message_type: u32) -> Result<Value> {
+    let mut socket = UnixStream::connect(path).await?;
+
+    let mut request = SWAY_IPC_MAGIC.to_vec();
+    request.extend_from_slice(&0u32.to_ne_bytes());
+    request.extend_from_slice(&message_type.to_ne_bytes());
+    socket.write_all(&request).await?;
+
+    // Header consists of the magic string, the payload length and the payload type
+    let mut header = [0u8; 14];
+    socket.read_exact(&mut header).await?;
+    let length = u32::from_ne_bytes(header[6..10].try_into()?) as usize;
+    let mut payload = vec![0u8; length];
+    socket.read_exact(&mut payload).await?;
+
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+async fn sway_focused_app(path: &Path) -> Result<Option<FocusedApp>> {
+    let tree = sway_request(path, SWAY_IPC_GET_TREE).await?;
+    Ok(find_focused_node(&tree).map(|node| FocusedApp {
+        app_id: node["app_id"]
+            .as_str()
+            .or_else(|| node["window_properties"]["class"].as_str())
+            .unwrap_or_default()
+            .to_string(),
+        title: node["name"].as_str().unwrap_or_default().to_string(),
+    }))
+}
+
+async fn hyprland_request(signature: &OsStr, command: &str) -> Result<Value> {
+    with_timeout(hyprland_request_inner(signature, command)).await
+}
+
+async fn hyprland_request_inner(signature: &OsStr, command: &str) -> Result<Value> {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/tmp"));
+    // Older versions of hyprland place their socket in /tmp
+    let candidates = [runtime_dir.join("hypr"), PathBuf::from("/tmp/hypr")]
+        .map(|dir| dir.join(signature).join(".socket.sock"));
+    let Some(path) = candidates.iter().find(|x| x.exists()) else {
+        bail!("Could not find hyprland socket");
+    };
+
+    let mut socket = UnixStream::connect(path).await?;
+    socket.write_all(command.as_bytes()).await?;
+    let mut response = vec![];
+    socket.read_to_end(&mut response).await?;
+
+    Ok(serde_json::from_slice(&response)?)
+}
+
+async fn hyprland_focused_app(signature: &OsStr) -> Result<Option<FocusedApp>> {
+    let window = hyprland_request(signature, "j/activewindow").await?;
+    Ok(window["class"].as_str().map(|class| FocusedApp {
+        app_id: class.to_string(),
+        title: window["title"].as_str().unwrap_or_default().to_string(),
+    }))
+}
+
+#[derive(Debug, Default)]
+struct Toplevel {
+    app_id: String,
+    title: String,
+    activated: bool,
+}
+
+#[derive(Debug, Default)]
+struct ToplevelState {
+    toplevels: HashMap<ObjectId, Toplevel>,
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for ToplevelState {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for ToplevelState {
+    fn event(
+        _: &mut Self,
+        _: &ZwlrForeignToplevelManagerV1,
+        _: zwlr_foreign_toplevel_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+
+    event_created_child!(ToplevelState, ZwlrForeignToplevelManagerV1, [
+        zwlr_foreign_toplevel_manager_v1::EVT_TOPLEVEL_OPCODE => (ZwlrForeignToplevelHandleV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for ToplevelState {
+    fn event(
+        state: &mut Self,
+        handle: &ZwlrForeignToplevelHandleV1,
+        event: zwlr_foreign_toplevel_handle_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let toplevel = state.toplevels.entry(handle.id()).or_default();
+        match event {
+            zwlr_foreign_toplevel_handle_v1::Event::AppId { app_id } => toplevel.app_id = app_id,
+            zwlr_foreign_toplevel_handle_v1::Event::Title { title } => toplevel.title = title,
+            zwlr_foreign_toplevel_handle_v1::Event::State { state } => {
+                let activated = zwlr_foreign_toplevel_handle_v1::State::Activated as u32;
+                toplevel.activated = state
+                    .chunks_exact(4)
+                    .any(|x| u32::from_ne_bytes([x[0], x[1], x[2], x[3]]) == activated);
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::Closed => {
+                state.toplevels.remove(&handle.id());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Determines the focused application using the wlr-foreign-toplevel-management protocol.
+/// Returns `None` if the compositor doesn't support it.
+fn toplevel_focused_app() -> Result<Option<FocusedApp>> {
+    let connection = Connection::connect_to_env()?;
+    let (globals, mut queue) = registry_queue_init::<ToplevelState>(&connection)?;
+    let qh = queue.handle();
+    let Ok(manager) = globals.bind::<ZwlrForeignToplevelManagerV1, _, _>(&qh, 1..=3, ()) else {
+        return Ok(None);
+    };
+
+    // The existing toplevels and their properties are announced right after binding
+    let mut state = ToplevelState::default();
+    queue.roundtrip(&mut state)?;
+    manager.stop();
+
+    Ok(state
+        .toplevels
+        .into_values()
+        .find(|x| x.activated)
+        .map(|x| FocusedApp {
+            app_id: x.app_id,
+            title: x.title,
+        }))
+}
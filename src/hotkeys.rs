@@ -1,7 +1,6 @@
 use std::{
     collections::HashMap,
     path::{Path, PathBuf},
-    str::FromStr,
     time::Duration,
 };
 
@@ -9,30 +8,38 @@ use color_eyre::eyre::Result;
 use evdev::{Device, InputEventKind, Key};
 use gtk::glib;
 use notify::{event::CreateKind, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use thiserror::Error;
 use tokio::sync::mpsc::{self, channel};
+use tracing::{error, info, warn};
 
 use crate::runtime;
 
+#[derive(Debug, Error)]
+pub enum HotkeyError {
+    #[error("Could not watch /dev/input for new devices: {0}")]
+    Watch(#[from] notify::Error),
+}
+
 #[derive(Debug)]
 pub enum HotkeyEvent {
-    Pressed,
-    Released,
+    Pressed(Key),
+    Released(Key),
 }
 
+#[tracing::instrument(name = "device", skip_all, fields(path = %path.display()))]
 pub async fn evdev_listen_device(
     sender: mpsc::Sender<HotkeyEvent>,
     path: PathBuf,
     device: Device,
-    key: Key,
+    keys: Vec<Key>,
 ) {
-    let name = device.name().unwrap_or("Unnamed device");
-    let name = format!("{} ({})", name, path.display());
+    let name = device.name().unwrap_or("Unnamed device").to_string();
 
-    println!("listening for events on {}", name);
+    info!(name = %name, "Listening for events");
     let mut events = match device.into_event_stream() {
         Ok(events) => events,
         Err(e) => {
-            eprintln!("Error while starting event stream on {}: {}", name, e);
+            error!(name = %name, "Error while starting event stream: {e}");
             return;
         }
     };
@@ -40,59 +47,63 @@ pub async fn evdev_listen_device(
         let ev = match events.next_event().await {
             Ok(ev) => ev,
             Err(e) => {
-                eprintln!(
-                    "Error while processing events on {} (device disconnected?): {}",
-                    name, e
+                warn!(
+                    name = %name,
+                    "Error while processing events (device disconnected?): {e}"
                 );
                 return;
             }
         };
 
         if let InputEventKind::Key(k) = ev.kind() {
-            if k == key {
+            if keys.contains(&k) {
                 if ev.value() == 0 {
-                    let _ = sender.send(HotkeyEvent::Released).await;
+                    let _ = sender.send(HotkeyEvent::Released(k)).await;
                 } else if ev.value() == 1 {
-                    let _ = sender.send(HotkeyEvent::Pressed).await;
+                    let _ = sender.send(HotkeyEvent::Pressed(k)).await;
                 }
             }
         }
     }
 }
 
-pub async fn register_and_watch(sender: mpsc::Sender<HotkeyEvent>, hotkey: String) {
-    let key = Key::from_str(&hotkey).expect(&format!("Could not find key with name {hotkey}"));
+pub async fn register_and_watch(
+    sender: mpsc::Sender<HotkeyEvent>,
+    keys: Vec<Key>,
+) -> Result<(), HotkeyError> {
+    let mut found = false;
     evdev::enumerate()
         .filter(|(_, device)| {
-            device
-                .supported_keys()
-                .map_or(false, |keys| keys.contains(key))
+            device.supported_keys().map_or(false, |supported| {
+                keys.iter().any(|&k| supported.contains(k))
+            })
         })
         .for_each(|(path, device)| {
-            runtime().spawn(glib::clone!(@strong sender => async move {
-                evdev_listen_device(sender, path, device, key).await;
+            found = true;
+            runtime().spawn(glib::clone!(@strong sender, @strong keys => async move {
+                evdev_listen_device(sender, path, device, keys).await;
             }));
         });
+    if !found {
+        warn!("No accessible input device supports the configured hotkeys, is your user in the input group?");
+    }
 
     // Watch for new devices in /dev/input
     let (tx, mut rx) = channel(1);
     let mut watcher = RecommendedWatcher::new(
         move |res| {
-            tx.blocking_send(res).unwrap();
+            // Fails only once the receiver below is gone
+            let _ = tx.blocking_send(res);
         },
         notify::Config::default(),
-    )
-    .expect("Failed to setup /dev/input watcher");
-
-    watcher
-        .watch(Path::new("/dev/input"), RecursiveMode::NonRecursive)
-        .expect("Failed to watch /dev/input");
+    )?;
+    watcher.watch(Path::new("/dev/input"), RecursiveMode::NonRecursive)?;
 
     let mut wait_for_permissions = HashMap::new();
     let try_spawn_listener = |path: PathBuf| -> Result<()> {
         let device = Device::open(&path)?;
-        runtime().spawn(glib::clone!(@strong sender => async move {
-            evdev_listen_device(sender, path, device, key).await;
+        runtime().spawn(glib::clone!(@strong sender, @strong keys => async move {
+            evdev_listen_device(sender, path, device, keys).await;
         }));
 
         Ok(())
@@ -119,7 +130,7 @@ pub async fn register_and_watch(sender: mpsc::Sender<HotkeyEvent>, hotkey: Strin
                             }
                             _ => {}
                         },
-                        Err(e) => eprintln!("watch error: {:?}", e),
+                        Err(e) => warn!("Error while watching /dev/input: {e:?}"),
                     }
                 } else {
                     break;
@@ -142,4 +153,6 @@ pub async fn register_and_watch(sender: mpsc::Sender<HotkeyEvent>, hotkey: Strin
             }
         }
     }
+
+    Ok(())
 }
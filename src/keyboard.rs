@@ -1,20 +1,462 @@
-use color_eyre::eyre::Result;
-use enigo::{Enigo, Keyboard, Settings};
+use clap::ValueEnum;
+use color_eyre::eyre::{bail, eyre, Report, Result};
+use enigo::{Direction, Enigo, Key, Keyboard, Settings};
+use serde::Deserialize;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
 use tokio::sync::mpsc;
+use tracing::{error, warn};
 
 use crate::runtime;
+use crate::wayland::WaylandTyper;
 
-pub fn spawn_virtual_keyboard(mut virtual_keyboard_receiver: mpsc::Receiver<String>) -> Result<()> {
-    runtime().spawn(async move {
-        while let Some(line) = virtual_keyboard_receiver.recv().await {
-            // Don't ask why we do this each time. Sometimes the wayland connection
-            // breaks and this allows us to be more robust.
-            let mut enigo = Enigo::new(&Settings::default()).unwrap();
-            if let Err(e) = enigo.text(&line) {
-                eprintln!("Failed to type text: {e}")
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TypingBackend {
+    /// Use the first backend that works, starting with virtual-keyboard
+    #[default]
+    Auto,
+    /// Type using the virtual-keyboard-v1 wayland protocol
+    VirtualKeyboard,
+    /// Commit text directly into the focused text field using the input-method-v2 wayland protocol
+    InputMethod,
+    /// Type using enigo
+    Enigo,
+    /// Type by running wtype
+    Wtype,
+    /// Type by running ydotool, which requires ydotoold to be running
+    Ydotool,
+}
+
+impl TypingBackend {
+    /// Returns the backends to try in order, starting with the selected one.
+    fn with_fallbacks(self) -> Vec<TypingBackend> {
+        let fallbacks = [
+            TypingBackend::VirtualKeyboard,
+            TypingBackend::Enigo,
+            TypingBackend::Wtype,
+            TypingBackend::Ydotool,
+        ];
+        std::iter::once(self)
+            .filter(|x| *x != TypingBackend::Auto)
+            .chain(fallbacks.into_iter().filter(|x| *x != self))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TypingOpts {
+    /// The delay between two key presses in milliseconds
+    pub key_delay_ms: u64,
+    /// Text is typed in chunks of this many characters. A running
+    /// typing operation can only be cancelled between two chunks.
+    pub chunk_size: usize,
+    /// The delay between two chunks in milliseconds
+    pub chunk_delay_ms: u64,
+    /// Text with at least this many characters is pasted through the
+    /// clipboard instead of being typed. Set to 0 to never paste. The
+    /// previous clipboard content is restored after pasting.
+    pub paste_threshold: usize,
+}
+
+impl Default for TypingOpts {
+    fn default() -> Self {
+        Self {
+            key_delay_ms: 0,
+            chunk_size: 64,
+            chunk_delay_ms: 0,
+            paste_threshold: 0,
+        }
+    }
+}
+
+/// An action performed by the keyboard thread
+#[derive(Debug)]
+pub enum KeyboardAction {
+    /// Type the given text
+    Type(String),
+    /// Press backspace the given number of times
+    Erase(usize),
+}
+
+/// The keys that are pressed by a single backend invocation
+enum Keys<'a> {
+    Text(&'a str),
+    Backspace(usize),
+    /// Ctrl+V
+    Paste,
+}
+
+#[derive(Debug, Error)]
+pub enum KeyboardError {
+    #[error("The keyboard thread has stopped")]
+    Stopped,
+}
+
+/// The error of a typing backend. Only if it failed before pressing any
+/// key, the keys can be pressed again with another backend.
+#[derive(Debug, Error)]
+pub enum BackendError {
+    #[error("{0:#}")]
+    Unavailable(Report),
+    #[error("{0:#}")]
+    Failed(Report),
+}
+
+impl BackendError {
+    pub fn new(error: impl Into<Report>, pressed: bool) -> Self {
+        if pressed {
+            Self::Failed(error.into())
+        } else {
+            Self::Unavailable(error.into())
+        }
+    }
+}
+
+/// Sends actions to the keyboard thread
+#[derive(Debug, Clone)]
+pub struct KeyboardSender {
+    sender: mpsc::Sender<(u64, KeyboardAction)>,
+    generation: Arc<AtomicU64>,
+}
+
+impl KeyboardSender {
+    pub async fn send(&self, action: KeyboardAction) -> Result<(), KeyboardError> {
+        let generation = self.generation.load(Ordering::SeqCst);
+        self.sender
+            .send((generation, action))
+            .await
+            .map_err(|_| KeyboardError::Stopped)
+    }
+
+    /// Cancels all actions sent so far, including the one that is currently typed.
+    /// Queued actions are dropped as well, since they belong to the session that
+    /// is being cancelled and must not be typed into whatever is focused later.
+    pub fn cancel(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Errors printed by typing commands which fail before pressing any key: wtype
+/// without a compositor that supports virtual keyboards, and ydotool without
+/// a running ydotoold.
+const UNAVAILABLE_ERRORS: &[&str] = &[
+    "Wayland connection failed",
+    "Compositor does not support the virtual keyboard protocol",
+    "failed to connect socket",
+];
+
+/// Runs a typing command. If it can't be started or reports one of the
+/// [`UNAVAILABLE_ERRORS`], no keys were pressed.
+fn run_command(program: &str, args: &[&str]) -> Result<(), BackendError> {
+    let output = Command::new(program)
+        .args(args)
+        .stdout(Stdio::null())
+        .output()
+        .map_err(|e| BackendError::new(e, false))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let pressed = !UNAVAILABLE_ERRORS.iter().any(|x| stderr.contains(x));
+        return Err(BackendError::new(
+            eyre!("{program} exited with {}: {}", output.status, stderr.trim()),
+            pressed,
+        ));
+    }
+    Ok(())
+}
+
+/// The time given to the focused application to read the pasted text before
+/// the previous clipboard content is restored
+const CLIPBOARD_RESTORE_DELAY: Duration = Duration::from_millis(300);
+
+/// The content of the clipboard with its mime type
+struct Clipboard {
+    mime_type: String,
+    content: Vec<u8>,
+}
+
+/// Returns the current clipboard content, or `None` if the clipboard is empty.
+fn get_clipboard() -> Result<Option<Clipboard>> {
+    let types = Command::new("wl-paste").arg("--list-types").output()?;
+    let types = String::from_utf8_lossy(&types.stdout);
+    let Some(mime_type) = types.lines().next() else {
+        return Ok(None);
+    };
+
+    let output = Command::new("wl-paste")
+        .args(["--no-newline", "--type", mime_type])
+        .output()?;
+    if !output.status.success() {
+        bail!("wl-paste exited with {}", output.status);
+    }
+    Ok(Some(Clipboard {
+        mime_type: mime_type.to_string(),
+        content: output.stdout,
+    }))
+}
+
+fn set_clipboard(content: &[u8], mime_type: Option<&str>) -> Result<()> {
+    let mut command = Command::new("wl-copy");
+    if let Some(mime_type) = mime_type {
+        command.args(["--type", mime_type]);
+    }
+    let mut child = command.stdin(Stdio::piped()).spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(content)?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("wl-copy exited with {status}");
+    }
+    Ok(())
+}
+
+fn restore_clipboard(clipboard: Option<Clipboard>) -> Result<()> {
+    match clipboard {
+        Some(clipboard) => set_clipboard(&clipboard.content, Some(&clipboard.mime_type)),
+        None => {
+            let status = Command::new("wl-copy").arg("--clear").status()?;
+            if !status.success() {
+                bail!("wl-copy exited with {status}");
+            }
+            Ok(())
+        }
+    }
+}
+
+struct Typer {
+    backends: Vec<TypingBackend>,
+    wayland: Option<WaylandTyper>,
+    opts: TypingOpts,
+    generation: Arc<AtomicU64>,
+}
+
+impl Typer {
+    fn key_delay(&self) -> Duration {
+        Duration::from_millis(self.opts.key_delay_ms)
+    }
+
+    fn cancelled(&self, generation: u64) -> bool {
+        self.generation.load(Ordering::SeqCst) != generation
+    }
+
+    fn run_wayland(&mut self, backend: TypingBackend, keys: &Keys) -> Result<(), BackendError> {
+        let key_delay = self.key_delay();
+
+        // The wayland connection is kept open between lines, but it can break
+        // (e.g. when the compositor kills our virtual keyboard). In that case,
+        // we reconnect once before giving up on this backend, unless keys
+        // were already pressed.
+        let mut result = Ok(());
+        for _ in 0..2 {
+            let wayland = match self.wayland.take() {
+                Some(wayland) => wayland,
+                None => WaylandTyper::connect().map_err(|e| BackendError::new(e, false))?,
+            };
+            let wayland = self.wayland.insert(wayland);
+
+            result = match (backend, keys) {
+                (TypingBackend::InputMethod, Keys::Text(text)) => wayland.commit_input_method(text),
+                (TypingBackend::InputMethod, _) => Err(BackendError::new(
+                    eyre!("the input method can only commit text"),
+                    false,
+                )),
+                (_, Keys::Text(text)) => wayland.type_virtual_keyboard(text, key_delay),
+                (_, Keys::Backspace(n)) => {
+                    wayland.type_virtual_keyboard(&"\u{8}".repeat(*n), key_delay)
+                }
+                (_, Keys::Paste) => wayland.paste(),
+            };
+            match result {
+                Ok(()) | Err(BackendError::Failed(_)) => break,
+                Err(BackendError::Unavailable(_)) => self.wayland = None,
+            }
+        }
+
+        result
+    }
+
+    fn run_enigo(&mut self, keys: &Keys) -> Result<(), BackendError> {
+        let enigo = Enigo::new(&Settings::default())
+            .map_err(|e| BackendError::new(eyre!("Could not connect: {e}"), false))?;
+        self.press_enigo(enigo, keys)
+            .map_err(|e| BackendError::new(e, true))
+    }
+
+    fn press_enigo(&mut self, mut enigo: Enigo, keys: &Keys) -> Result<()> {
+        let key_delay = self.key_delay();
+        match keys {
+            Keys::Text(text) if key_delay.is_zero() => enigo.text(text).map_err(|e| eyre!("{e}")),
+            Keys::Text(text) => {
+                for c in text.chars() {
+                    enigo.text(&c.to_string()).map_err(|e| eyre!("{e}"))?;
+                    std::thread::sleep(key_delay);
+                }
+                Ok(())
+            }
+            Keys::Backspace(n) => {
+                for _ in 0..*n {
+                    enigo
+                        .key(Key::Backspace, Direction::Click)
+                        .map_err(|e| eyre!("{e}"))?;
+                    std::thread::sleep(key_delay);
+                }
+                Ok(())
+            }
+            Keys::Paste => {
+                enigo
+                    .key(Key::Control, Direction::Press)
+                    .map_err(|e| eyre!("{e}"))?;
+                let result = enigo.key(Key::Unicode('v'), Direction::Click);
+                enigo
+                    .key(Key::Control, Direction::Release)
+                    .map_err(|e| eyre!("{e}"))?;
+                result.map_err(|e| eyre!("{e}"))
+            }
+        }
+    }
+
+    fn run_with(&mut self, backend: TypingBackend, keys: &Keys) -> Result<(), BackendError> {
+        let key_delay = self.opts.key_delay_ms.to_string();
+        match (backend, keys) {
+            (
+                TypingBackend::Auto | TypingBackend::VirtualKeyboard | TypingBackend::InputMethod,
+                _,
+            ) => self.run_wayland(backend, keys),
+            (TypingBackend::Enigo, _) => self.run_enigo(keys),
+            (TypingBackend::Wtype, Keys::Text(text)) => {
+                run_command("wtype", &["-d", &key_delay, "--", text])
+            }
+            (TypingBackend::Wtype, Keys::Backspace(n)) => {
+                let mut args = vec!["-d", key_delay.as_str()];
+                args.extend(["-k", "BackSpace"].repeat(*n));
+                run_command("wtype", &args)
+            }
+            (TypingBackend::Wtype, Keys::Paste) => {
+                run_command("wtype", &["-M", "ctrl", "-k", "v", "-m", "ctrl"])
+            }
+            (TypingBackend::Ydotool, Keys::Text(text)) => {
+                run_command("ydotool", &["type", "--key-delay", &key_delay, "--", text])
+            }
+            (TypingBackend::Ydotool, Keys::Backspace(n)) => {
+                // 14 is the evdev keycode of backspace
+                let mut args = vec!["key", "--key-delay", key_delay.as_str()];
+                args.extend(["14:1", "14:0"].repeat(*n));
+                run_command("ydotool", &args)
+            }
+            (TypingBackend::Ydotool, Keys::Paste) => {
+                // 29 and 47 are the evdev keycodes of left control and v
+                run_command("ydotool", &["key", "29:1", "47:1", "47:0", "29:0"])
+            }
+        }
+    }
+
+    /// Presses the keys with the first backend that is available. Once a backend
+    /// has started pressing keys, its failure isn't retried with another backend,
+    /// as that would type the keys pressed so far twice.
+    fn press(&mut self, keys: &Keys) -> Result<(), BackendError> {
+        for backend in self.backends.clone() {
+            match self.run_with(backend, keys) {
+                Ok(()) => return Ok(()),
+                Err(BackendError::Unavailable(e)) => {
+                    warn!(?backend, "Typing backend is unavailable: {e:#}")
+                }
+                Err(BackendError::Failed(e)) => {
+                    return Err(BackendError::Failed(
+                        e.wrap_err(format!("Typing backend {backend:?} failed")),
+                    ))
+                }
+            }
+        }
+
+        Err(BackendError::Unavailable(eyre!(
+            "all typing backends are unavailable"
+        )))
+    }
+
+    fn paste(&mut self, text: &str) -> Result<(), BackendError> {
+        let previous = get_clipboard().map_err(|e| BackendError::new(e, false))?;
+        set_clipboard(text.as_bytes(), None).map_err(|e| BackendError::new(e, false))?;
+        let result = self.press(&Keys::Paste);
+
+        std::thread::sleep(CLIPBOARD_RESTORE_DELAY);
+        if let Err(e) = restore_clipboard(previous) {
+            warn!("Could not restore the clipboard: {e:#}");
+        }
+        result
+    }
+
+    fn type_text(&mut self, generation: u64, text: &str) -> Result<()> {
+        let chars: Vec<char> = text.chars().collect();
+        let threshold = self.opts.paste_threshold;
+        if threshold > 0 && chars.len() >= threshold {
+            match self.paste(text) {
+                Ok(()) => return Ok(()),
+                Err(BackendError::Unavailable(e)) => {
+                    warn!("Failed to paste text, typing it instead: {e:#}")
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let chunk_delay = Duration::from_millis(self.opts.chunk_delay_ms);
+        let chunk_size = self.opts.chunk_size.max(1);
+        for (i, chunk) in chars.chunks(chunk_size).enumerate() {
+            if i > 0 {
+                std::thread::sleep(chunk_delay);
+            }
+            if self.cancelled(generation) {
+                return Ok(());
+            }
+
+            let chunk: String = chunk.iter().collect();
+            self.press(&Keys::Text(&chunk))?;
+        }
+
+        Ok(())
+    }
+
+    fn run(&mut self, generation: u64, action: &KeyboardAction) -> Result<()> {
+        if self.cancelled(generation) {
+            return Ok(());
+        }
+
+        match action {
+            KeyboardAction::Type(text) => self.type_text(generation, text),
+            KeyboardAction::Erase(n) => Ok(self.press(&Keys::Backspace(*n))?),
+        }
+    }
+}
+
+/// Starts the keyboard thread. Failures to type are logged and sent to `errors`.
+pub fn spawn_virtual_keyboard(
+    backend: TypingBackend,
+    opts: TypingOpts,
+    errors: mpsc::Sender<String>,
+) -> KeyboardSender {
+    let (sender, mut receiver) = mpsc::channel(64);
+    let generation = Arc::new(AtomicU64::new(0));
+
+    let mut typer = Typer {
+        backends: backend.with_fallbacks(),
+        wayland: None,
+        opts,
+        generation: generation.clone(),
+    };
+    runtime().spawn_blocking(move || {
+        while let Some((generation, action)) = receiver.blocking_recv() {
+            if let Err(e) = typer.run(generation, &action) {
+                error!("Failed to type text: {e:#}");
+                // Never block typing on the user interface
+                let _ = errors.try_send(format!("Failed to type text: {e:#}"));
             }
         }
     });
 
-    Ok(())
+    KeyboardSender { sender, generation }
 }
@@ -0,0 +1,244 @@
+use serde::Deserialize;
+use std::collections::VecDeque;
+
+/// Words whose start times differ by less than this (in seconds)
+/// are considered to be the same word across realtime updates.
+const BEGIN_TOLERANCE: f32 = 0.3;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LiveTypingOpts {
+    /// Type words while speaking, before the final result is available
+    pub enabled: bool,
+    /// The number of consecutive realtime updates in which a word must
+    /// be unchanged before it is typed
+    pub stable_updates: usize,
+}
+
+impl Default for LiveTypingOpts {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            stable_updates: 3,
+        }
+    }
+}
+
+/// Live typing options of a profile. Unset options are kept.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LiveTypingOverrides {
+    pub enabled: Option<bool>,
+    pub stable_updates: Option<usize>,
+}
+
+impl LiveTypingOverrides {
+    pub fn apply(&self, opts: &mut LiveTypingOpts) {
+        if let Some(enabled) = self.enabled {
+            opts.enabled = enabled;
+        }
+        if let Some(stable_updates) = self.stable_updates {
+            opts.stable_updates = stable_updates;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LiveWord {
+    pub text: String,
+    pub begin: f32,
+}
+
+/// Keeps track of the words that were already typed from realtime results
+/// of the current utterance.
+#[derive(Debug)]
+pub struct LiveTyper {
+    stable_updates: usize,
+    updates: VecDeque<Vec<LiveWord>>,
+    typed_words: usize,
+    typed: String,
+}
+
+impl LiveTyper {
+    pub fn new(stable_updates: usize) -> Self {
+        Self {
+            stable_updates: stable_updates.max(1),
+            updates: VecDeque::new(),
+            typed_words: 0,
+            typed: String::new(),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.updates.clear();
+        self.typed_words = 0;
+        self.typed.clear();
+    }
+
+    /// Processes the words of a realtime result and returns the text
+    /// that has become stable and should be typed now.
+    pub fn update(&mut self, words: Vec<LiveWord>) -> Option<String> {
+        self.updates.push_back(words);
+        while self.updates.len() > self.stable_updates {
+            self.updates.pop_front();
+        }
+        if self.updates.len() < self.stable_updates {
+            return None;
+        }
+
+        // The number of leading words that were unchanged in all recent updates
+        let latest = self.updates.back()?;
+        let stable = latest
+            .iter()
+            .enumerate()
+            .take_while(|(i, word)| {
+                self.updates.iter().all(|update| {
+                    update.get(*i).is_some_and(|x| {
+                        x.text == word.text && (x.begin - word.begin).abs() < BEGIN_TOLERANCE
+                    })
+                })
+            })
+            .count();
+        if stable <= self.typed_words {
+            return None;
+        }
+
+        let mut text = String::new();
+        for word in &latest[self.typed_words..stable] {
+            if self.typed.is_empty() && text.is_empty() {
+                text += word.text.trim_start();
+            } else {
+                text += &word.text;
+            }
+        }
+
+        self.typed_words = stable;
+        self.typed += &text;
+        Some(text)
+    }
+
+    /// Reconciles the text typed so far with the final result. Returns the
+    /// number of characters that must be erased from the end of the typed text,
+    /// and the text that must be typed afterwards.
+    pub fn finish(&mut self, result: &str) -> (usize, String) {
+        let common = self
+            .typed
+            .chars()
+            .zip(result.chars())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let erase = self.typed.chars().count() - common;
+        let rest = result.chars().skip(common).collect();
+
+        self.reset();
+        (erase, rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(text: &[(&str, f32)]) -> Vec<LiveWord> {
+        text.iter()
+            .map(|(text, begin)| LiveWord {
+                text: text.to_string(),
+                begin: *begin,
+            })
+            .collect()
+    }
+
+    /// Feeds the same words until they are stable and returns the typed text
+    fn type_stable(typer: &mut LiveTyper, text: &[(&str, f32)]) -> Option<String> {
+        let mut typed = None;
+        for _ in 0..typer.stable_updates {
+            typed = typer.update(words(text));
+        }
+        typed
+    }
+
+    #[test]
+    fn types_words_once_stable() {
+        let mut typer = LiveTyper::new(2);
+        assert_eq!(typer.update(words(&[(" Hello", 0.0)])), None);
+        assert_eq!(
+            typer.update(words(&[(" Hello", 0.1), (" world", 0.5)])),
+            Some("Hello".to_string())
+        );
+        assert_eq!(
+            typer.update(words(&[(" Hello", 0.1), (" world", 0.5)])),
+            Some(" world".to_string())
+        );
+        assert_eq!(typer.finish("Hello world"), (0, String::new()));
+    }
+
+    #[test]
+    fn finish_erases_from_divergence_in_the_middle() {
+        let mut typer = LiveTyper::new(1);
+        let typed = typer.update(words(&[(" I", 0.0), (" scream", 0.2), (" loudly", 0.6)]));
+        assert_eq!(typed.as_deref(), Some("I scream loudly"));
+        assert_eq!(
+            typer.finish("Ice cream loudly"),
+            (14, "ce cream loudly".to_string())
+        );
+    }
+
+    #[test]
+    fn finish_fixes_capitalization_only() {
+        let mut typer = LiveTyper::new(1);
+        typer.update(words(&[(" hello", 0.0), (" world", 0.4)]));
+        assert_eq!(typer.finish("Hello world"), (11, "Hello world".to_string()));
+
+        let mut typer = LiveTyper::new(1);
+        typer.update(words(&[(" Hello", 0.0), (" world", 0.4)]));
+        assert_eq!(typer.finish("Hello World"), (5, "World".to_string()));
+    }
+
+    #[test]
+    fn stable_word_revised_later() {
+        let mut typer = LiveTyper::new(2);
+        let typed = type_stable(&mut typer, &[(" The", 0.0), (" wether", 0.3)]);
+        assert_eq!(typed.as_deref(), Some("The wether"));
+
+        // A later revision of a typed word is not typed again, only the new words are
+        assert_eq!(
+            typer.update(words(&[(" The", 0.0), (" weather", 0.3), (" is", 0.8)])),
+            None
+        );
+        assert_eq!(
+            typer.update(words(&[(" The", 0.0), (" weather", 0.3), (" is", 0.8)])),
+            Some(" is".to_string())
+        );
+
+        // The final result corrects it
+        assert_eq!(
+            typer.finish("The weather is nice"),
+            (7, "ather is nice".to_string())
+        );
+    }
+
+    #[test]
+    fn multiple_segments_with_newlines() {
+        let mut typer = LiveTyper::new(1);
+        let typed = typer.update(words(&[(" Hello", 0.0), (" world.", 0.4), (" How", 1.5)]));
+        assert_eq!(typed.as_deref(), Some("Hello world. How"));
+
+        // With trailing newlines the segments are separated by newlines instead of spaces
+        assert_eq!(
+            typer.finish("Hello world.\nHow are you?\n"),
+            (4, "\nHow are you?\n".to_string())
+        );
+
+        // The next utterance starts from scratch
+        let typed = typer.update(words(&[(" Fine", 0.0)]));
+        assert_eq!(typed.as_deref(), Some("Fine"));
+    }
+
+    #[test]
+    fn finish_without_live_typing() {
+        let mut typer = LiveTyper::new(3);
+        assert_eq!(typer.update(words(&[(" Hello", 0.0)])), None);
+        assert_eq!(typer.finish("Hello there"), (0, "Hello there".to_string()));
+        assert_eq!(typer.finish(""), (0, String::new()));
+    }
+}
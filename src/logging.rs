@@ -0,0 +1,78 @@
+use color_eyre::eyre::{Context, Result};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::EnvFilter;
+
+use crate::cli::LogOpts;
+
+/// Whether transcribed text may appear in log messages
+static LOG_TRANSCRIPTS: AtomicBool = AtomicBool::new(false);
+
+/// Sets up logging to stderr or the systemd journal
+pub fn init(opts: &LogOpts) -> Result<()> {
+    LOG_TRANSCRIPTS.store(opts.log_transcripts, Ordering::Relaxed);
+
+    let filter = match &opts.log_level {
+        Some(level) => {
+            EnvFilter::try_new(level).wrap_err_with(|| format!("Invalid log level {level:?}"))?
+        }
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+
+    let registry = tracing_subscriber::registry().with(filter);
+    if opts.journald {
+        let journald = tracing_journald::layer().wrap_err("Could not connect to the journal")?;
+        registry.with(journald).init();
+    } else {
+        // Stdout is reserved for output, such as the status or transcribed text
+        registry
+            .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+            .init();
+    }
+
+    Ok(())
+}
+
+/// Transcribed text, which is only shown in log messages if enabled by `--log-transcripts`
+pub struct Redacted<'a> {
+    text: &'a str,
+    show: bool,
+}
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.show {
+            write!(f, "{:?}", self.text)
+        } else {
+            write!(f, "<{} characters redacted>", self.text.chars().count())
+        }
+    }
+}
+
+pub fn redact(text: &str) -> Redacted<'_> {
+    Redacted {
+        text,
+        show: LOG_TRANSCRIPTS.load(Ordering::Relaxed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_unless_transcripts_are_logged() {
+        let redacted = Redacted {
+            text: "Grüße, world",
+            show: false,
+        };
+        assert_eq!(redacted.to_string(), "<12 characters redacted>");
+
+        let shown = Redacted {
+            text: "Grüße, \"world\"",
+            show: true,
+        };
+        assert_eq!(shown.to_string(), r#""Grüße, \"world\"""#);
+    }
+}
@@ -5,10 +5,27 @@ use tokio::runtime::Runtime;
 
 mod app;
 mod cli;
+mod config;
+mod control;
+mod dbus;
+mod display;
+mod focus;
 mod hotkeys;
 mod keyboard;
+mod live;
+mod logging;
+mod metrics;
+mod notification;
+mod output;
+mod overlay;
+mod profile;
+mod rules;
+mod service;
+mod sound;
+mod theme;
 mod util;
 mod waybar;
+mod wayland;
 
 pub fn runtime() -> &'static Runtime {
     static RUNTIME: OnceLock<Runtime> = OnceLock::new();
@@ -16,16 +33,46 @@ pub fn runtime() -> &'static Runtime {
 }
 
 fn main() -> Result<()> {
+    // Taken before the runtime starts any threads, as this modifies the environment
+    let control_listener = service::activated_listener();
     color_eyre::install()?;
     let args = cli::Cli::parse();
+    logging::init(&args.log_opts)?;
 
     match args.command {
-        cli::Command::WaybarStatus { connection_opts } => {
-            runtime()
-                .block_on(async move { waybar::main_waybar_status(&connection_opts).await })?;
+        cli::Command::WaybarStatus {
+            connection_opts,
+            config,
+            format,
+        } => {
+            let opts = config::load_config(config.as_deref())?.waybar;
+            runtime().block_on(async move {
+                waybar::main_waybar_status(&connection_opts, opts, format).await
+            })?;
+        }
+        cli::Command::ServerStatus {
+            connection_opts,
+            config,
+            json,
+        } => {
+            let opts = config::load_config(config.as_deref())?.waybar;
+            runtime().block_on(async move {
+                waybar::main_server_status(&connection_opts, &opts, json).await
+            })?;
+        }
+        cli::Command::Ctl { request } => {
+            runtime().block_on(control::main_ctl(request))?;
+        }
+        cli::Command::InstallService {
+            dir,
+            server_command,
+            server_listen,
+            overlay_args,
+        } => {
+            service::main_install_service(dir, server_command, &server_listen, &overlay_args)?;
         }
         command @ cli::Command::Overlay { .. } => {
-            app::launch_app(command)?;
+            app::launch_app(command, control_listener)?;
         }
     }
 
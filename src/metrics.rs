@@ -0,0 +1,269 @@
+use color_eyre::eyre::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tracing::warn;
+
+/// The upper bounds of the histogram buckets in seconds
+const BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// The statistics of this overlay instance
+pub static METRICS: Metrics = Metrics::new();
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MetricsOpts {
+    /// The address on which metrics are served in the OpenMetrics text format,
+    /// such as "127.0.0.1:9633". Metrics are not served if unset. The endpoint
+    /// has no authentication and should only listen on a loopback address.
+    pub listen: Option<String>,
+}
+
+/// A counter of a floating point value
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn add(&self, value: f64) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| {
+                Some((f64::from_bits(x) + value).to_bits())
+            });
+    }
+
+    pub fn inc(&self) {
+        self.add(1.0);
+    }
+
+    fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Default)]
+struct HistogramState {
+    /// The number of observations in each bucket, not cumulative
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+pub struct Histogram(Mutex<HistogramState>);
+
+impl Histogram {
+    const fn new() -> Self {
+        Self(Mutex::new(HistogramState {
+            counts: Vec::new(),
+            sum: 0.0,
+            count: 0,
+        }))
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let value = duration.as_secs_f64();
+        let mut state = self.0.lock().expect("Could not lock histogram");
+        state.counts.resize(BUCKETS.len(), 0);
+        if let Some(i) = BUCKETS.iter().position(|x| value <= *x) {
+            state.counts[i] += 1;
+        }
+        state.sum += value;
+        state.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        let state = self.0.lock().expect("Could not lock histogram");
+        let mut cumulative = 0;
+        for (i, bound) in BUCKETS.iter().enumerate() {
+            cumulative += state.counts.get(i).copied().unwrap_or(0);
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", state.count);
+        let _ = writeln!(out, "{name}_sum {}", state.sum);
+        let _ = writeln!(out, "{name}_count {}", state.count);
+    }
+}
+
+pub struct Metrics {
+    /// Sessions in which the model lock was acquired
+    pub sessions: Counter,
+    /// Seconds of audio sent to the server
+    pub audio_seconds: Counter,
+    /// Words that were typed into applications
+    pub words_typed: Counter,
+    errors: Mutex<BTreeMap<&'static str, u64>>,
+    /// The time spent waiting for the model lock
+    pub lock_wait: Histogram,
+    /// The time from the end of a recording until its final result arrived
+    pub final_latency: Histogram,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            sessions: Counter::new(),
+            audio_seconds: Counter::new(),
+            words_typed: Counter::new(),
+            errors: Mutex::new(BTreeMap::new()),
+            lock_wait: Histogram::new(),
+            final_latency: Histogram::new(),
+        }
+    }
+
+    /// Counts an error of the given kind, such as "connect" or "protocol"
+    pub fn error(&self, kind: &'static str) {
+        *self
+            .errors
+            .lock()
+            .expect("Could not lock error counters")
+            .entry(kind)
+            .or_default() += 1;
+    }
+
+    /// Renders all metrics in the OpenMetrics text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            (
+                "sessions",
+                "Sessions in which the model lock was acquired",
+                &self.sessions,
+            ),
+            (
+                "audio_seconds",
+                "Seconds of audio sent to the server",
+                &self.audio_seconds,
+            ),
+            (
+                "words_typed",
+                "Words typed into applications",
+                &self.words_typed,
+            ),
+        ];
+        for (name, help, counter) in counters {
+            let _ = writeln!(out, "# TYPE whisper_overlay_{name} counter");
+            let _ = writeln!(out, "# HELP whisper_overlay_{name} {help}");
+            let _ = writeln!(out, "whisper_overlay_{name}_total {}", counter.get());
+        }
+
+        let _ = writeln!(out, "# TYPE whisper_overlay_errors counter");
+        let _ = writeln!(out, "# HELP whisper_overlay_errors Errors by kind");
+        for (kind, count) in self
+            .errors
+            .lock()
+            .expect("Could not lock error counters")
+            .iter()
+        {
+            let _ = writeln!(
+                out,
+                "whisper_overlay_errors_total{{kind=\"{kind}\"}} {count}"
+            );
+        }
+
+        let histograms = [
+            (
+                "lock_wait_seconds",
+                "Time spent waiting for the model lock",
+                &self.lock_wait,
+            ),
+            (
+                "final_latency_seconds",
+                "Time from the end of a recording until its final result",
+                &self.final_latency,
+            ),
+        ];
+        for (name, help, histogram) in histograms {
+            let _ = writeln!(out, "# TYPE whisper_overlay_{name} histogram");
+            let _ = writeln!(out, "# HELP whisper_overlay_{name} {help}");
+            histogram.render(&mut out, &format!("whisper_overlay_{name}"));
+        }
+
+        out += "# EOF\n";
+        out
+    }
+}
+
+/// The time given to a client to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves the metrics over http on the given address. Every request is answered
+/// with the metrics, regardless of its path. Anyone who can reach the address
+/// can read them, so a warning is logged unless it is a loopback address.
+pub async fn serve(listen: &str) -> Result<()> {
+    let listener = TcpListener::bind(listen)
+        .await
+        .wrap_err_with(|| format!("Could not bind metrics endpoint {listen}"))?;
+    if !listener.local_addr()?.ip().is_loopback() {
+        warn!(%listen, "The metrics endpoint is reachable from other machines, it has no authentication");
+    }
+
+    loop {
+        let (mut socket, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            // The request itself is irrelevant, but must be read before replying
+            let mut request = [0u8; 1024];
+            if tokio::time::timeout(REQUEST_TIMEOUT, socket.read(&mut request))
+                .await
+                .is_err()
+            {
+                return;
+            }
+
+            let body = METRICS.render();
+            let response = format!(
+                "HTTP/1.0 200 OK\r\nContent-Type: application/openmetrics-text; version=1.0.0; charset=utf-8\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            );
+            if let Err(e) = socket.write_all(response.as_bytes()).await {
+                warn!("Could not send metrics: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_openmetrics() {
+        let metrics = Metrics::new();
+        metrics.sessions.inc();
+        metrics.audio_seconds.add(2.5);
+        metrics.error("connect");
+        metrics.error("connect");
+        metrics.error("audio");
+        metrics.lock_wait.observe(Duration::from_millis(200));
+        metrics.lock_wait.observe(Duration::from_secs(120));
+
+        let out = metrics.render();
+        let lines: Vec<_> = out.lines().collect();
+        for expected in [
+            "# TYPE whisper_overlay_sessions counter",
+            "whisper_overlay_sessions_total 1",
+            "whisper_overlay_audio_seconds_total 2.5",
+            "whisper_overlay_words_typed_total 0",
+            "whisper_overlay_errors_total{kind=\"audio\"} 1",
+            "whisper_overlay_errors_total{kind=\"connect\"} 2",
+            "# TYPE whisper_overlay_lock_wait_seconds histogram",
+            "whisper_overlay_lock_wait_seconds_bucket{le=\"0.1\"} 0",
+            "whisper_overlay_lock_wait_seconds_bucket{le=\"0.25\"} 1",
+            "whisper_overlay_lock_wait_seconds_bucket{le=\"60\"} 1",
+            "whisper_overlay_lock_wait_seconds_bucket{le=\"+Inf\"} 2",
+            "whisper_overlay_lock_wait_seconds_count 2",
+            "whisper_overlay_final_latency_seconds_bucket{le=\"+Inf\"} 0",
+            "whisper_overlay_final_latency_seconds_count 0",
+        ] {
+            assert!(lines.contains(&expected), "missing {expected:?} in\n{out}");
+        }
+        assert_eq!(lines.last(), Some(&"# EOF"));
+    }
+}
@@ -0,0 +1,142 @@
+use color_eyre::eyre::{eyre, Result};
+use gtk::{gio, glib, prelude::*};
+use serde::Deserialize;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use tracing::warn;
+
+const NOTIFICATIONS_NAME: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct NotificationOpts {
+    /// Show desktop notifications for session events
+    pub enabled: bool,
+    /// Notify about connection, server and typing errors.
+    /// Repeated errors are only announced once a minute.
+    pub errors: bool,
+    /// Notify if waiting for the model lock takes longer than this many
+    /// milliseconds, which means that the server is busy. Set to 0 to disable.
+    pub lock_wait_ms: u64,
+}
+
+impl Default for NotificationOpts {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            errors: true,
+            lock_wait_ms: 3000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Urgency {
+    Normal = 1,
+    Critical = 2,
+}
+
+/// Sends notifications over D-Bus. Each notification replaces the previous
+/// one, so at most one notification of the overlay is shown at a time.
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    opts: NotificationOpts,
+    id: Rc<Cell<u32>>,
+}
+
+impl Notifier {
+    pub fn new(opts: NotificationOpts) -> Self {
+        Self {
+            opts,
+            id: Rc::default(),
+        }
+    }
+
+    pub fn opts(&self) -> &NotificationOpts {
+        &self.opts
+    }
+
+    pub fn notify(&self, summary: &str, body: &str, urgency: Urgency) {
+        if !self.opts.enabled {
+            return;
+        }
+
+        let id = self.id.clone();
+        let (summary, body) = (summary.to_string(), body.to_string());
+        glib::spawn_future_local(async move {
+            match send_notification(id.get(), &summary, &body, urgency).await {
+                Ok(new_id) => id.set(new_id),
+                Err(e) => warn!("Could not send notification: {e:#}"),
+            }
+        });
+    }
+
+    /// Closes the last notification, if it is still shown.
+    pub fn close(&self) {
+        let id = self.id.replace(0);
+        if id == 0 {
+            return;
+        }
+
+        glib::spawn_future_local(async move {
+            if let Err(e) = call(
+                "CloseNotification",
+                (id,).to_variant(),
+                glib::VariantTy::UNIT,
+            )
+            .await
+            {
+                warn!("Could not close notification: {e:#}");
+            }
+        });
+    }
+}
+
+async fn call(
+    method: &str,
+    parameters: glib::Variant,
+    reply_type: &glib::VariantTy,
+) -> Result<glib::Variant> {
+    let connection = gio::bus_get_future(gio::BusType::Session).await?;
+    Ok(connection
+        .call_future(
+            Some(NOTIFICATIONS_NAME),
+            NOTIFICATIONS_PATH,
+            NOTIFICATIONS_NAME,
+            method,
+            Some(&parameters),
+            Some(reply_type),
+            gio::DBusCallFlags::NONE,
+            1000,
+        )
+        .await?)
+}
+
+async fn send_notification(
+    replaces_id: u32,
+    summary: &str,
+    body: &str,
+    urgency: Urgency,
+) -> Result<u32> {
+    let hints = HashMap::from([("urgency".to_string(), (urgency as u8).to_variant())]);
+    let parameters = (
+        "whisper-overlay",
+        replaces_id,
+        "audio-input-microphone",
+        summary,
+        body,
+        Vec::<String>::new(),
+        hints,
+        -1i32,
+    )
+        .to_variant();
+
+    let reply_type = glib::VariantTy::new("(u)").expect("valid variant type");
+    let reply = call("Notify", parameters, reply_type).await?;
+    let (id,) = reply
+        .get::<(u32,)>()
+        .ok_or_else(|| eyre!("unexpected reply {reply}"))?;
+    Ok(id)
+}
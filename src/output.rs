@@ -0,0 +1,166 @@
+use serde::Deserialize;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputSink {
+    /// Type the transcribed text into the focused window
+    #[default]
+    Type,
+    /// Print the transcribed text to stdout
+    Stdout,
+    /// Only display the transcribed text in the overlay
+    None,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PostProcessOpts {
+    /// End each transcribed line with a newline. If disabled, each line
+    /// ends with a space instead, so that consecutive results are separated.
+    pub trailing_newline: bool,
+    /// Keep the capitalization of the first letter of each line as
+    /// produced by the model. If disabled, it will be lowercased.
+    pub capitalize: bool,
+}
+
+impl Default for PostProcessOpts {
+    fn default() -> Self {
+        Self {
+            trailing_newline: true,
+            capitalize: true,
+        }
+    }
+}
+
+/// Shows the final result in the overlay for review instead of outputting it immediately.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ReviewOpts {
+    pub enabled: bool,
+    /// Accept the text automatically after this many milliseconds
+    pub timeout_ms: Option<u64>,
+}
+
+/// Post-processing options of a profile or rule. Unset options are kept.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PostProcessOverrides {
+    pub trailing_newline: Option<bool>,
+    pub capitalize: Option<bool>,
+}
+
+impl PostProcessOverrides {
+    pub fn apply(&self, opts: &mut PostProcessOpts) {
+        if let Some(trailing_newline) = self.trailing_newline {
+            opts.trailing_newline = trailing_newline;
+        }
+        if let Some(capitalize) = self.capitalize {
+            opts.capitalize = capitalize;
+        }
+    }
+}
+
+/// Review options of a profile or rule. Unset options are kept.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ReviewOverrides {
+    pub enabled: Option<bool>,
+    pub timeout_ms: Option<u64>,
+}
+
+impl ReviewOverrides {
+    pub fn apply(&self, opts: &mut ReviewOpts) {
+        if let Some(enabled) = self.enabled {
+            opts.enabled = enabled;
+        }
+        if self.timeout_ms.is_some() {
+            opts.timeout_ms = self.timeout_ms;
+        }
+    }
+}
+
+fn lowercase_first(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Turns the transcribed lines of a result into the text that should be output.
+pub fn post_process(lines: &[String], opts: &PostProcessOpts) -> String {
+    let lines = lines
+        .iter()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            if opts.capitalize {
+                line.to_string()
+            } else {
+                lowercase_first(line)
+            }
+        });
+
+    let separator = if opts.trailing_newline { "\n" } else { " " };
+    lines.map(|line| line + separator).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn trailing_newline_ends_each_line() {
+        let opts = PostProcessOpts::default();
+        assert_eq!(
+            post_process(&lines(&[" Hello there.", " How are you? "]), &opts),
+            "Hello there.\nHow are you?\n"
+        );
+    }
+
+    #[test]
+    fn without_trailing_newline_lines_end_with_a_space() {
+        let opts = PostProcessOpts {
+            trailing_newline: false,
+            ..PostProcessOpts::default()
+        };
+        assert_eq!(
+            post_process(&lines(&[" Hello there.", " How are you?"]), &opts),
+            "Hello there. How are you? "
+        );
+    }
+
+    #[test]
+    fn consecutive_results_are_separated() {
+        let opts = PostProcessOpts {
+            trailing_newline: false,
+            ..PostProcessOpts::default()
+        };
+        let typed =
+            post_process(&lines(&[" Hello."]), &opts) + &post_process(&lines(&[" World."]), &opts);
+        assert_eq!(typed, "Hello. World. ");
+    }
+
+    #[test]
+    fn lowercases_first_letter_unless_capitalized() {
+        let opts = PostProcessOpts {
+            capitalize: false,
+            ..PostProcessOpts::default()
+        };
+        assert_eq!(
+            post_process(&lines(&[" Hello World", " Über"]), &opts),
+            "hello World\nüber\n"
+        );
+    }
+
+    #[test]
+    fn empty_lines_are_dropped() {
+        let opts = PostProcessOpts::default();
+        assert_eq!(post_process(&lines(&["", "  ", " Hi"]), &opts), "Hi\n");
+        assert_eq!(post_process(&lines(&[" "]), &opts), "");
+    }
+}
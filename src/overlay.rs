@@ -0,0 +1,271 @@
+use gtk::prelude::*;
+use gtk::ApplicationWindow;
+use gtk_layer_shell::{Edge, Layer, LayerShell};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::display::DisplayMode;
+use crate::focus::{cursor_position, focused_output};
+use crate::theme::ThemeOpts;
+
+/// The distance between the mouse cursor and the overlay in cursor mode
+const CURSOR_OFFSET: i32 = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverlayEdge {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverlayLayer {
+    /// Above all windows, including fullscreen windows
+    #[default]
+    Overlay,
+    /// Above normal windows, but below fullscreen windows
+    Top,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Margins {
+    pub top: i32,
+    pub bottom: i32,
+    pub left: i32,
+    pub right: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawWidth {
+    Pixels(i32),
+    Text(String),
+}
+
+/// The width of the overlay, either in pixels or as a percentage of the output width
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "RawWidth")]
+pub enum OverlayWidth {
+    Pixels(i32),
+    Percent(f64),
+}
+
+impl TryFrom<RawWidth> for OverlayWidth {
+    type Error = String;
+
+    fn try_from(value: RawWidth) -> Result<Self, Self::Error> {
+        match value {
+            RawWidth::Pixels(pixels) => Ok(OverlayWidth::Pixels(pixels)),
+            RawWidth::Text(text) => text
+                .strip_suffix('%')
+                .and_then(|x| x.trim().parse().ok())
+                .map(OverlayWidth::Percent)
+                .ok_or_else(|| format!("invalid width {text:?}, expected pixels or a percentage")),
+        }
+    }
+}
+
+/// Selects the output (monitor) on which the overlay is shown
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(from = "String")]
+pub enum OutputSelection {
+    /// Let the compositor decide
+    #[default]
+    Auto,
+    /// The output that has keyboard focus
+    Focused,
+    /// The output that contains the mouse cursor (hyprland only, others use the focused output)
+    Cursor,
+    /// The output with the given connector name, such as DP-1
+    Named(String),
+}
+
+impl From<String> for OutputSelection {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "auto" => OutputSelection::Auto,
+            "focused" => OutputSelection::Focused,
+            "cursor" => OutputSelection::Cursor,
+            _ => OutputSelection::Named(value),
+        }
+    }
+}
+
+/// Settings for the appearance and behavior of the overlay window
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct OverlayOpts {
+    /// Words with a lower probability are underlined and can
+    /// be corrected while the text is pending review
+    pub low_confidence_threshold: f32,
+    /// The maximum number of visible lines of text. Older lines are scrolled
+    /// out of view. Set to 0 to show all lines.
+    pub max_lines: usize,
+    /// How long finalized lines stay visible in milliseconds
+    pub history_timeout_ms: u64,
+    /// How long expired lines take to fade out in milliseconds
+    pub fade_ms: u64,
+    /// The screen edges to which the overlay is anchored
+    pub anchor: Vec<OverlayEdge>,
+    /// The distance to the anchored edges in pixels
+    pub margin: Margins,
+    pub width: OverlayWidth,
+    pub layer: OverlayLayer,
+    /// The output on which the overlay is shown. This is determined
+    /// again each time a session is started.
+    pub output: OutputSelection,
+    /// The layout of the overlay, which can be cycled at runtime with the display mode hotkey
+    pub display_mode: DisplayMode,
+    pub theme: ThemeOpts,
+}
+
+impl Default for OverlayOpts {
+    fn default() -> Self {
+        Self {
+            low_confidence_threshold: 0.5,
+            max_lines: 5,
+            history_timeout_ms: 6000,
+            fade_ms: 1000,
+            anchor: vec![OverlayEdge::Bottom],
+            margin: Margins {
+                bottom: 200,
+                ..Margins::default()
+            },
+            width: OverlayWidth::Pixels(1600),
+            layer: OverlayLayer::Overlay,
+            output: OutputSelection::Auto,
+            display_mode: DisplayMode::Full,
+            theme: ThemeOpts::default(),
+        }
+    }
+}
+
+/// Resolves the output selection to the connector name of an output.
+/// Returns `None` if the compositor should decide.
+pub async fn resolve_output(selection: &OutputSelection) -> Option<String> {
+    let output = match selection {
+        OutputSelection::Auto => return None,
+        OutputSelection::Named(name) => return Some(name.clone()),
+        OutputSelection::Focused => focused_output(false).await,
+        OutputSelection::Cursor => focused_output(true).await,
+    };
+
+    output.unwrap_or_else(|e| {
+        warn!("Could not determine the focused output: {e}");
+        None
+    })
+}
+
+/// Where the overlay is shown during a session
+#[derive(Debug, Default, Clone)]
+pub struct Placement {
+    /// The connector name of the output, or `None` to let the compositor decide
+    pub output: Option<String>,
+    /// The cursor position relative to the output, if the overlay should follow the cursor
+    pub cursor: Option<(i32, i32)>,
+}
+
+/// Determines where the overlay should be shown for the given display mode.
+/// In cursor mode, this falls back to the output selection if the cursor
+/// position is not available.
+pub async fn resolve_placement(selection: &OutputSelection, mode: DisplayMode) -> Placement {
+    if mode == DisplayMode::Cursor {
+        match cursor_position().await {
+            Ok(Some((output, x, y))) => {
+                return Placement {
+                    output: Some(output),
+                    cursor: Some((x, y)),
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Could not determine the cursor position: {e}"),
+        }
+    }
+
+    Placement {
+        output: resolve_output(selection).await,
+        cursor: None,
+    }
+}
+
+fn find_monitor(name: &str) -> Option<gdk::Monitor> {
+    let monitors = gdk::Display::default()?.monitors();
+    (0..monitors.n_items())
+        .filter_map(|i| monitors.item(i)?.downcast::<gdk::Monitor>().ok())
+        .find(|x| x.connector().is_some_and(|x| x == name))
+}
+
+fn first_monitor() -> Option<gdk::Monitor> {
+    gdk::Display::default()?
+        .monitors()
+        .item(0)?
+        .downcast::<gdk::Monitor>()
+        .ok()
+}
+
+/// Applies the layer and the initial placement of the overlay.
+pub fn init_placement(window: &ApplicationWindow, opts: &OverlayOpts) {
+    window.set_layer(match opts.layer {
+        OverlayLayer::Overlay => Layer::Overlay,
+        OverlayLayer::Top => Layer::Top,
+    });
+
+    place_window(window, opts, opts.display_mode, &Placement::default());
+}
+
+/// Moves the overlay to the given placement and updates its anchors and width
+/// for the display mode. If no output is given, the compositor decides where
+/// the overlay is shown.
+pub fn place_window(
+    window: &ApplicationWindow,
+    opts: &OverlayOpts,
+    mode: DisplayMode,
+    placement: &Placement,
+) {
+    match placement.cursor.filter(|_| mode == DisplayMode::Cursor) {
+        Some((x, y)) => {
+            for edge in [Edge::Top, Edge::Bottom, Edge::Left, Edge::Right] {
+                window.set_anchor(edge, matches!(edge, Edge::Top | Edge::Left));
+                window.set_margin(edge, 0);
+            }
+            window.set_margin(Edge::Left, x);
+            window.set_margin(Edge::Top, y + CURSOR_OFFSET);
+        }
+        None => {
+            for (edge, anchor, margin) in [
+                (Edge::Top, OverlayEdge::Top, opts.margin.top),
+                (Edge::Bottom, OverlayEdge::Bottom, opts.margin.bottom),
+                (Edge::Left, OverlayEdge::Left, opts.margin.left),
+                (Edge::Right, OverlayEdge::Right, opts.margin.right),
+            ] {
+                window.set_anchor(edge, opts.anchor.contains(&anchor));
+                window.set_margin(edge, margin);
+            }
+        }
+    }
+
+    let monitor = placement.output.as_deref().and_then(|name| {
+        let monitor = find_monitor(name);
+        if monitor.is_none() {
+            warn!("Could not find output {name}");
+        }
+        monitor
+    });
+    if let Some(monitor) = &monitor {
+        window.set_monitor(monitor);
+    }
+
+    let width = match (mode, opts.width) {
+        // Compact layouts take their natural width
+        (DisplayMode::Pill | DisplayMode::Cursor, _) => -1,
+        (_, OverlayWidth::Pixels(pixels)) => pixels,
+        (_, OverlayWidth::Percent(percent)) => monitor.or_else(first_monitor).map_or(1600, |x| {
+            (x.geometry().width() as f64 * percent / 100.0) as i32
+        }),
+    };
+    window.set_default_width(width);
+}
@@ -0,0 +1,206 @@
+use color_eyre::eyre::{bail, Result};
+use evdev::Key;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::cli::{ConnectionOpts, SessionOpts};
+use crate::config::Config;
+use crate::live::LiveTypingOpts;
+use crate::output::{OutputSink, PostProcessOpts, ReviewOpts};
+
+/// The settings used for all sessions started by a specific hotkey.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub hotkey: Key,
+    pub connection_opts: ConnectionOpts,
+    pub session_opts: SessionOpts,
+    pub output: OutputSink,
+    pub post_process: PostProcessOpts,
+    pub review: ReviewOpts,
+    pub live_typing: LiveTypingOpts,
+}
+
+#[derive(Debug, Error)]
+pub enum KeyError {
+    #[error("Could not find key with name {0}")]
+    UnknownKey(String),
+}
+
+pub fn parse_key(name: &str) -> Result<Key, KeyError> {
+    Key::from_str(name).map_err(|_| KeyError::UnknownKey(name.to_string()))
+}
+
+/// Resolves all profiles defined in the configuration, using the given default
+/// profile to fill any unset options. The default profile is kept unless a
+/// configured profile is bound to the same hotkey, in which case it is replaced.
+pub fn resolve_profiles(default: Profile, config: &Config) -> Result<Vec<Profile>> {
+    let mut profiles: Vec<Profile> = Vec::new();
+    for (name, profile) in &config.profiles {
+        let hotkey = parse_key(&profile.hotkey)?;
+        if let Some(other) = profiles.iter().find(|x| x.hotkey == hotkey) {
+            bail!(
+                "Profiles {} and {} are bound to the same hotkey {:?}",
+                other.name,
+                name,
+                hotkey
+            );
+        }
+
+        let mut post_process = default.post_process.clone();
+        profile.post_process.apply(&mut post_process);
+        let mut review = default.review.clone();
+        profile.review.apply(&mut review);
+        let mut live_typing = default.live_typing.clone();
+        profile.live_typing.apply(&mut live_typing);
+
+        profiles.push(Profile {
+            name: name.clone(),
+            hotkey,
+            connection_opts: ConnectionOpts {
+                address: profile
+                    .address
+                    .clone()
+                    .unwrap_or_else(|| default.connection_opts.address.clone()),
+            },
+            session_opts: profile.session.clone().or(&default.session_opts),
+            output: profile.output.unwrap_or(default.output),
+            post_process,
+            review,
+            live_typing,
+        });
+    }
+
+    if !profiles.iter().any(|x| x.hotkey == default.hotkey) {
+        profiles.insert(0, default);
+    }
+
+    Ok(profiles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Task;
+
+    fn default_profile() -> Profile {
+        Profile {
+            name: "default".to_string(),
+            hotkey: Key::KEY_RIGHTCTRL,
+            connection_opts: ConnectionOpts {
+                address: "localhost:7007".to_string(),
+            },
+            session_opts: SessionOpts {
+                language: Some("en".to_string()),
+                initial_prompt: Some("Hello.".to_string()),
+                hotwords: vec!["whisper".to_string()],
+                task: None,
+            },
+            output: OutputSink::Type,
+            post_process: PostProcessOpts::default(),
+            review: ReviewOpts::default(),
+            live_typing: LiveTypingOpts::default(),
+        }
+    }
+
+    fn config(config: &str) -> Config {
+        toml::from_str(config).expect("valid config")
+    }
+
+    #[test]
+    fn session_opts_fall_back_per_option() {
+        let cli = SessionOpts {
+            language: Some("de".to_string()),
+            ..SessionOpts::default()
+        };
+        let merged = cli.or(&default_profile().session_opts);
+        assert_eq!(merged.language.as_deref(), Some("de"));
+        assert_eq!(merged.initial_prompt.as_deref(), Some("Hello."));
+        assert_eq!(merged.hotwords, ["whisper"]);
+        assert_eq!(merged.task, None);
+    }
+
+    #[test]
+    fn auto_language_overrides_fallback_but_is_not_requested() {
+        let cli = SessionOpts {
+            language: Some("auto".to_string()),
+            ..SessionOpts::default()
+        };
+        assert_eq!(cli.or(&default_profile().session_opts).language, None);
+    }
+
+    #[test]
+    fn profiles_fall_back_to_default() {
+        let config = config(
+            r#"
+            [profiles.translate]
+            hotkey = "KEY_F9"
+            address = "gpu:7007"
+            output = "stdout"
+            session = { task = "translate", hotwords = ["kubectl"] }
+            "#,
+        );
+        let profiles = resolve_profiles(default_profile(), &config).expect("valid profiles");
+        assert_eq!(
+            profiles.iter().map(|x| x.name.as_str()).collect::<Vec<_>>(),
+            ["default", "translate"]
+        );
+
+        let profile = &profiles[1];
+        assert_eq!(profile.hotkey, Key::KEY_F9);
+        assert_eq!(profile.connection_opts.address, "gpu:7007");
+        assert_eq!(profile.output, OutputSink::Stdout);
+        assert_eq!(profile.session_opts.task, Some(Task::Translate));
+        assert_eq!(profile.session_opts.hotwords, ["kubectl"]);
+        assert_eq!(profile.session_opts.language.as_deref(), Some("en"));
+        assert!(profile.post_process.trailing_newline);
+    }
+
+    #[test]
+    fn profile_options_fall_back_per_field() {
+        let config = config(
+            r#"
+            [profiles.chat]
+            hotkey = "KEY_F9"
+            post-process = { trailing-newline = false }
+            live-typing = { enabled = true }
+            "#,
+        );
+        let mut default = default_profile();
+        default.post_process.capitalize = false;
+        default.live_typing.stable_updates = 5;
+        let profiles = resolve_profiles(default, &config).expect("valid profiles");
+
+        let profile = &profiles[1];
+        assert!(!profile.post_process.trailing_newline);
+        assert!(!profile.post_process.capitalize);
+        assert!(profile.live_typing.enabled);
+        assert_eq!(profile.live_typing.stable_updates, 5);
+    }
+
+    #[test]
+    fn profile_replaces_default_with_same_hotkey() {
+        let config = config(
+            r#"
+            [profiles.main]
+            hotkey = "KEY_RIGHTCTRL"
+            "#,
+        );
+        let profiles = resolve_profiles(default_profile(), &config).expect("valid profiles");
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].name, "main");
+    }
+
+    #[test]
+    fn profiles_with_same_hotkey_are_rejected() {
+        let config = config(
+            r#"
+            [profiles.a]
+            hotkey = "KEY_F9"
+            [profiles.b]
+            hotkey = "KEY_F9"
+            "#,
+        );
+        assert!(resolve_profiles(default_profile(), &config).is_err());
+    }
+}
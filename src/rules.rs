@@ -0,0 +1,155 @@
+use color_eyre::eyre::{bail, Result};
+use serde::Deserialize;
+
+use crate::focus::FocusedApp;
+use crate::output::{OutputSink, PostProcessOverrides, ReviewOverrides};
+use crate::profile::Profile;
+
+/// Overrides the output of a session depending on the focused application.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct AppRule {
+    /// Matches the wayland app id (or X11 window class), ignoring case
+    pub app_id: Option<String>,
+    /// Matches if the window title contains this text, ignoring case
+    pub title: Option<String>,
+    /// Don't start any sessions while this application is focused
+    #[serde(default)]
+    pub disable: bool,
+    pub output: Option<OutputSink>,
+    pub post_process: Option<PostProcessOverrides>,
+    pub review: Option<ReviewOverrides>,
+}
+
+impl AppRule {
+    /// Rejects rules without any matcher, which would apply to every application
+    pub fn validate(&self) -> Result<()> {
+        if self.app_id.is_none() && self.title.is_none() {
+            bail!("every rule needs an app-id or a title to match");
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, app: &FocusedApp) -> bool {
+        let app_id_matches = self
+            .app_id
+            .as_ref()
+            .map_or(true, |x| x.eq_ignore_ascii_case(&app.app_id));
+        let title_matches = self.title.as_ref().map_or(true, |x| {
+            app.title.to_lowercase().contains(&x.to_lowercase())
+        });
+        app_id_matches && title_matches
+    }
+
+    pub fn apply(&self, profile: &mut Profile) {
+        if let Some(output) = self.output {
+            profile.output = output;
+        }
+        if let Some(post_process) = &self.post_process {
+            post_process.apply(&mut profile.post_process);
+        }
+        if let Some(review) = &self.review {
+            review.apply(&mut profile.review);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{ConnectionOpts, SessionOpts};
+    use crate::live::LiveTypingOpts;
+    use crate::output::{PostProcessOpts, ReviewOpts};
+
+    fn rule(config: &str) -> AppRule {
+        toml::from_str(config).expect("valid rule")
+    }
+
+    fn app(app_id: &str, title: &str) -> FocusedApp {
+        FocusedApp {
+            app_id: app_id.to_string(),
+            title: title.to_string(),
+        }
+    }
+
+    fn profile() -> Profile {
+        Profile {
+            name: "default".to_string(),
+            hotkey: evdev::Key::KEY_RIGHTCTRL,
+            connection_opts: ConnectionOpts {
+                address: "localhost:7007".to_string(),
+            },
+            session_opts: SessionOpts::default(),
+            output: OutputSink::Type,
+            post_process: PostProcessOpts::default(),
+            review: ReviewOpts {
+                enabled: true,
+                timeout_ms: Some(1000),
+            },
+            live_typing: LiveTypingOpts::default(),
+        }
+    }
+
+    #[test]
+    fn matches_app_id_ignoring_case() {
+        let rule = rule(r#"app-id = "Firefox""#);
+        assert!(rule.matches(&app("firefox", "Some page")));
+        assert!(!rule.matches(&app("firefox-esr", "Some page")));
+    }
+
+    #[test]
+    fn matches_title_substring_ignoring_case() {
+        let rule = rule(r#"title = "password""#);
+        assert!(rule.matches(&app("firefox", "Enter your Password - Bank")));
+        assert!(!rule.matches(&app("firefox", "Bank")));
+    }
+
+    #[test]
+    fn matches_requires_all_matchers() {
+        let rule = rule(
+            r#"
+            app-id = "kitty"
+            title = "vim"
+            "#,
+        );
+        assert!(rule.matches(&app("kitty", "vim notes.txt")));
+        assert!(!rule.matches(&app("kitty", "zsh")));
+        assert!(!rule.matches(&app("foot", "vim notes.txt")));
+    }
+
+    #[test]
+    fn rules_without_matchers_are_invalid() {
+        assert!(rule("output = \"none\"").validate().is_err());
+        assert!(rule("title = \"x\"").validate().is_ok());
+    }
+
+    #[test]
+    fn apply_only_overrides_set_options() {
+        let rule = rule(
+            r#"
+            app-id = "kitty"
+            output = "stdout"
+            post-process = { trailing-newline = false }
+            review = { timeout-ms = 5000 }
+            "#,
+        );
+        let mut profile = profile();
+        rule.apply(&mut profile);
+
+        assert_eq!(profile.output, OutputSink::Stdout);
+        assert!(!profile.post_process.trailing_newline);
+        assert!(profile.post_process.capitalize);
+        assert!(profile.review.enabled);
+        assert_eq!(profile.review.timeout_ms, Some(5000));
+    }
+
+    #[test]
+    fn apply_without_options_keeps_profile() {
+        let mut profile = profile();
+        rule(r#"app-id = "kitty""#).apply(&mut profile);
+
+        assert_eq!(profile.output, OutputSink::Type);
+        assert!(profile.post_process.trailing_newline);
+        assert_eq!(profile.review.timeout_ms, Some(1000));
+    }
+}
@@ -0,0 +1,184 @@
+use color_eyre::eyre::{eyre, Context, Result};
+use gtk::glib;
+use sd_notify::NotifyState;
+use std::os::fd::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// The name of the control socket unit, which starts the overlay on first use
+const OVERLAY_SOCKET_UNIT: &str = "whisper-overlay.socket";
+
+/// Tells systemd that the overlay is ready. Does nothing if not started by systemd.
+pub fn notify_ready() {
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        warn!("Could not notify systemd about readiness: {e}");
+    }
+}
+
+/// Pings the systemd watchdog from the gtk main loop, so a blocked user interface
+/// causes the service to be restarted. Does nothing if the watchdog is disabled.
+pub fn start_watchdog() {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return;
+    }
+
+    info!(interval_ms = usec / 1000, "Enabling systemd watchdog");
+    glib::timeout_add_local(Duration::from_micros(usec / 2), || {
+        let _ = sd_notify::notify(false, &[NotifyState::Watchdog]);
+        glib::ControlFlow::Continue
+    });
+}
+
+/// Returns the listening socket passed by systemd if the overlay was socket activated.
+/// This must be called before any threads are started, as it removes the variables
+/// passed by systemd from the environment.
+pub fn activated_listener() -> Option<std::os::unix::net::UnixListener> {
+    let fd = sd_notify::listen_fds().ok()?.next()?;
+    // SAFETY: systemd passes us ownership of this listening socket, which
+    // is only taken once since the environment variables are removed.
+    Some(unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) })
+}
+
+/// Quotes an argument for the command line of a systemd unit
+fn quote(arg: &str) -> String {
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    if escaped.is_empty() || escaped.contains(char::is_whitespace) || escaped != arg {
+        format!("\"{escaped}\"")
+    } else {
+        escaped
+    }
+}
+
+fn overlay_units(exe: &Path, overlay_args: &[String]) -> Vec<(&'static str, String)> {
+    let command = std::iter::once(exe.to_string_lossy().to_string())
+        .chain(["--journald".to_string(), "overlay".to_string()])
+        .chain(overlay_args.iter().cloned())
+        .map(|x| quote(&x))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    vec![
+        (
+            "whisper-overlay.service",
+            format!(
+                "[Unit]
+Description=Speech to text overlay
+PartOf=graphical-session.target
+After=graphical-session.target
+
+[Service]
+Type=notify
+ExecStart={command}
+WatchdogSec=30
+Restart=on-failure
+
+[Install]
+WantedBy=graphical-session.target
+Also={OVERLAY_SOCKET_UNIT}
+"
+            ),
+        ),
+        (
+            OVERLAY_SOCKET_UNIT,
+            "[Unit]
+Description=Control socket of the speech to text overlay
+
+[Socket]
+ListenStream=%t/whisper-overlay.sock
+
+[Install]
+WantedBy=sockets.target
+"
+            .to_string(),
+        ),
+    ]
+}
+
+fn server_units(command: &str, listen: &str) -> Vec<(&'static str, String)> {
+    vec![
+        (
+            "realtime-stt-server.service",
+            format!(
+                "[Unit]
+Description=Realtime speech to text server
+Requires=realtime-stt-server.socket
+
+[Service]
+Type=notify
+ExecStart={command}
+# Loading the models can take a while, especially if they need to be downloaded first
+TimeoutStartSec=10min
+Restart=on-failure
+
+[Install]
+Also=realtime-stt-server.socket
+"
+            ),
+        ),
+        (
+            "realtime-stt-server.socket",
+            format!(
+                "[Unit]
+Description=Realtime speech to text server socket
+
+[Socket]
+ListenStream={listen}
+
+[Install]
+WantedBy=sockets.target
+"
+            ),
+        ),
+    ]
+}
+
+/// The directory of systemd user units
+fn unit_dir() -> Result<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|x| PathBuf::from(x).join(".config")))
+        .map(|x| x.join("systemd").join("user"))
+        .ok_or_else(|| eyre!("Neither XDG_CONFIG_HOME nor HOME is set"))
+}
+
+pub fn main_install_service(
+    dir: Option<PathBuf>,
+    server_command: Option<String>,
+    server_listen: &str,
+    overlay_args: &[String],
+) -> Result<()> {
+    let dir = match dir {
+        Some(dir) => dir,
+        None => unit_dir()?,
+    };
+    let exe = std::env::current_exe().wrap_err("Could not determine the path of this program")?;
+
+    let mut units = overlay_units(&exe, overlay_args);
+    if let Some(command) = &server_command {
+        units.extend(server_units(command, server_listen));
+    }
+
+    std::fs::create_dir_all(&dir)
+        .wrap_err_with(|| format!("Could not create {}", dir.display()))?;
+    for (name, content) in &units {
+        let path = dir.join(name);
+        std::fs::write(&path, content)
+            .wrap_err_with(|| format!("Could not write {}", path.display()))?;
+        println!("Created {}", path.display());
+    }
+
+    println!();
+    println!("Enable the units with:");
+    println!("  systemctl --user daemon-reload");
+    println!("  systemctl --user enable --now whisper-overlay.socket whisper-overlay.service");
+    if server_command.is_some() {
+        println!("  systemctl --user enable --now realtime-stt-server.socket");
+    }
+    Ok(())
+}
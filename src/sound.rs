@@ -0,0 +1,170 @@
+use color_eyre::eyre::{bail, eyre, Result};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SizedSample};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use tracing::warn;
+
+/// The duration of the fade in and out of each tone, which avoids clicks
+const TONE_FADE_SECONDS: f32 = 0.005;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Cue {
+    /// The microphone is recording
+    Start,
+    /// Recording has stopped and the final result is processed
+    Stop,
+    /// The result was typed or output
+    Result,
+    Error,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct SoundOpts {
+    /// Play short sounds for session events
+    pub enabled: bool,
+    /// The volume of the built-in sounds, from 0 to 1
+    pub volume: f32,
+    /// Sound files which replace the built-in sounds. They are played
+    /// with pw-play, or paplay if pw-play is not available.
+    pub start: Option<PathBuf>,
+    pub stop: Option<PathBuf>,
+    pub result: Option<PathBuf>,
+    pub error: Option<PathBuf>,
+}
+
+impl Default for SoundOpts {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            volume: 0.3,
+            start: None,
+            stop: None,
+            result: None,
+            error: None,
+        }
+    }
+}
+
+/// Plays sound cues on the default output device, independently of the capture stream
+#[derive(Debug, Clone)]
+pub struct SoundCues {
+    opts: SoundOpts,
+}
+
+impl SoundCues {
+    pub fn new(opts: SoundOpts) -> Self {
+        Self { opts }
+    }
+
+    pub fn play(&self, cue: Cue) {
+        if !self.opts.enabled {
+            return;
+        }
+
+        let file = match cue {
+            Cue::Start => &self.opts.start,
+            Cue::Stop => &self.opts.stop,
+            Cue::Result => &self.opts.result,
+            Cue::Error => &self.opts.error,
+        }
+        .clone();
+        let volume = self.opts.volume.clamp(0.0, 1.0);
+        std::thread::spawn(move || {
+            let result = match file {
+                Some(file) => play_file(&file),
+                None => play_tones(tones(cue), volume),
+            };
+            if let Err(e) = result {
+                warn!(?cue, "Could not play sound: {e:#}");
+            }
+        });
+    }
+}
+
+fn play_file(path: &Path) -> Result<()> {
+    for player in ["pw-play", "paplay"] {
+        match Command::new(player).arg(path).status() {
+            Ok(status) if status.success() => return Ok(()),
+            Ok(status) => bail!("{player} exited with {status}"),
+            // Try the next player if this one is not installed
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    bail!("neither pw-play nor paplay is available")
+}
+
+/// The frequencies (in Hz, 0 for silence) and durations of the built-in sounds
+fn tones(cue: Cue) -> &'static [(f32, Duration)] {
+    const fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    match cue {
+        Cue::Start => &[(660.0, ms(60)), (880.0, ms(80))],
+        Cue::Stop => &[(880.0, ms(60)), (660.0, ms(80))],
+        Cue::Result => &[(1320.0, ms(50))],
+        Cue::Error => &[(220.0, ms(120)), (0.0, ms(40)), (220.0, ms(120))],
+    }
+}
+
+fn render_tones(tones: &[(f32, Duration)], volume: f32, sample_rate: f32) -> Vec<f32> {
+    let mut samples = vec![];
+    for (frequency, duration) in tones {
+        let count = (duration.as_secs_f32() * sample_rate) as usize;
+        let length = duration.as_secs_f32();
+        samples.extend((0..count).map(|i| {
+            let t = i as f32 / sample_rate;
+            let envelope = (t.min(length - t) / TONE_FADE_SECONDS).clamp(0.0, 1.0);
+            (t * frequency * std::f32::consts::TAU).sin() * volume * envelope
+        }));
+    }
+    samples
+}
+
+fn play_tones(tones: &[(f32, Duration)], volume: f32) -> Result<()> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or_else(|| eyre!("No output device available"))?;
+    let config = device.default_output_config()?;
+    let samples = render_tones(tones, volume, config.sample_rate().0 as f32);
+    let duration: Duration = tones.iter().map(|(_, duration)| *duration).sum();
+
+    let stream = match config.sample_format() {
+        cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config.into(), samples)?,
+        cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config.into(), samples)?,
+        cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config.into(), samples)?,
+        format => bail!("Unsupported sample format {format}"),
+    };
+    stream.play()?;
+    // Leave some time for the buffered samples to be played
+    std::thread::sleep(duration + Duration::from_millis(100));
+    Ok(())
+}
+
+fn build_stream<T: SizedSample + FromSample<f32>>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    samples: Vec<f32>,
+) -> Result<cpal::Stream> {
+    let channels = config.channels as usize;
+    let mut position = 0;
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &_| {
+            for frame in data.chunks_mut(channels) {
+                let sample = samples.get(position).copied().unwrap_or(0.0);
+                position += 1;
+                frame.fill(T::from_sample(sample));
+            }
+        },
+        |err| warn!("An error occurred on the output stream: {err}"),
+        None,
+    )?;
+    Ok(stream)
+}
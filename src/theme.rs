@@ -0,0 +1,206 @@
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Result};
+use colorgrad::{Color, Gradient};
+use serde::Deserialize;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThemeName {
+    #[default]
+    Dark,
+    Light,
+    HighContrast,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IconSet {
+    /// Icons from a Nerd Font
+    #[default]
+    NerdFont,
+    /// Symbols that are available in most fonts
+    Unicode,
+    /// Don't show any icons
+    None,
+}
+
+/// Colors that override the ones of the selected theme
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ThemeColors {
+    /// Secondary information such as the language and profile
+    pub muted: Option<String>,
+    pub disconnected: Option<String>,
+    pub connecting: Option<String>,
+    pub locking: Option<String>,
+    pub connected: Option<String>,
+    /// The translation indicator
+    pub translate: Option<String>,
+    /// Hints shown while text is pending review
+    pub review: Option<String>,
+    /// The background of the selected word in review mode
+    pub selection: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ThemeOpts {
+    /// The built-in theme to start from
+    pub base: ThemeName,
+    /// Color each word by the confidence of the model. If disabled,
+    /// words use the text color of the stylesheet.
+    pub confidence_colors: bool,
+    /// The colors of the confidence gradient, from low to high confidence
+    pub gradient: Vec<String>,
+    pub icons: IconSet,
+    pub colors: ThemeColors,
+}
+
+impl Default for ThemeOpts {
+    fn default() -> Self {
+        Self {
+            base: ThemeName::Dark,
+            confidence_colors: true,
+            gradient: vec![],
+            icons: IconSet::NerdFont,
+            colors: ThemeColors::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Status {
+    Disconnected,
+    Connecting,
+    Locking,
+    Connected,
+}
+
+const DARK_GRADIENT: &[&str] = &[
+    "#fe0000", "#fb3209", "#f74811", "#f35918", "#ef671e", "#ea7423", "#e67f28", "#e18a2c",
+    "#dc9430", "#d79e34", "#d1a738", "#cbb03b", "#c4b93d", "#bcc23e", "#b2cc3d", "#a6d53a",
+    "#97df36", "#82e92e", "#62f321", "#00ff00",
+];
+const LIGHT_GRADIENT: &[&str] = &["#c62828", "#e65100", "#9e7700", "#558b2f", "#1b5e20"];
+const HIGH_CONTRAST_GRADIENT: &[&str] = &["#ff5050", "#ffff00", "#ffffff"];
+
+/// The resolved colors and icons used to render the overlay
+pub struct Theme {
+    pub name: ThemeName,
+    gradient: Option<Gradient>,
+    icons: IconSet,
+    pub muted: String,
+    disconnected: String,
+    connecting: String,
+    locking: String,
+    connected: String,
+    pub translate: String,
+    pub review: String,
+    pub selection: String,
+}
+
+fn parse_color(color: &str) -> Result<String> {
+    let color = Color::from_html(color).map_err(|e| eyre!("Invalid color {color}: {e}"))?;
+    Ok(color.to_hex_string())
+}
+
+impl Theme {
+    pub fn new(opts: &ThemeOpts) -> Result<Self> {
+        // muted, disconnected, connecting, locking, connected, translate, review, selection
+        let (defaults, gradient) = match opts.base {
+            ThemeName::Dark => (
+                [
+                    "#bebebe", "#bebebe", "yellow", "orange", "#4ab0fa", "#c678dd", "#e5c07b",
+                    "#3e4451",
+                ],
+                DARK_GRADIENT,
+            ),
+            ThemeName::Light => (
+                [
+                    "#6e7781", "#6e7781", "#9a6700", "#bc4c00", "#0969da", "#8250df", "#9a6700",
+                    "#d0d7de",
+                ],
+                LIGHT_GRADIENT,
+            ),
+            ThemeName::HighContrast => (
+                [
+                    "#ffffff", "#ffffff", "#ffff00", "#ff8000", "#00ffff", "#ff80ff", "#ffff00",
+                    "#0000ff",
+                ],
+                HIGH_CONTRAST_GRADIENT,
+            ),
+        };
+
+        let colors = &opts.colors;
+        let color = |custom: &Option<String>, default: &str| {
+            parse_color(custom.as_deref().unwrap_or(default))
+        };
+
+        let gradient = if !opts.confidence_colors {
+            None
+        } else if !opts.gradient.is_empty() {
+            let stops: Vec<&str> = opts.gradient.iter().map(String::as_str).collect();
+            Some(
+                colorgrad::CustomGradient::new()
+                    .html_colors(&stops)
+                    .build()?,
+            )
+        } else {
+            Some(
+                colorgrad::CustomGradient::new()
+                    .html_colors(gradient)
+                    .build()?,
+            )
+        };
+
+        Ok(Self {
+            name: opts.base,
+            gradient,
+            icons: opts.icons,
+            muted: color(&colors.muted, defaults[0])?,
+            disconnected: color(&colors.disconnected, defaults[1])?,
+            connecting: color(&colors.connecting, defaults[2])?,
+            locking: color(&colors.locking, defaults[3])?,
+            connected: color(&colors.connected, defaults[4])?,
+            translate: color(&colors.translate, defaults[5])?,
+            review: color(&colors.review, defaults[6])?,
+            selection: color(&colors.selection, defaults[7])?,
+        })
+    }
+
+    /// The css class added to the overlay, so stylesheets can adapt to the theme
+    pub fn css_class(&self) -> &'static str {
+        match self.name {
+            ThemeName::Dark => "theme-dark",
+            ThemeName::Light => "theme-light",
+            ThemeName::HighContrast => "theme-high-contrast",
+        }
+    }
+
+    /// The color of a word with the given probability, or `None`
+    /// if confidence coloring is disabled.
+    pub fn word_color(&self, probability: f32) -> Option<String> {
+        self.gradient
+            .as_ref()
+            .map(|x| x.at(probability.into()).to_hex_string())
+    }
+
+    /// Renders the given connection status with its icon.
+    pub fn status_markup(&self, status: Status, text: &str) -> String {
+        let color = match status {
+            Status::Disconnected => &self.disconnected,
+            Status::Connecting => &self.connecting,
+            Status::Locking => &self.locking,
+            Status::Connected => &self.connected,
+        };
+        let icon = match (self.icons, status) {
+            (IconSet::NerdFont, _) => "\u{f444}",
+            (IconSet::Unicode, Status::Disconnected) => "\u{25cb}",
+            (IconSet::Unicode, Status::Connecting) => "\u{25cc}",
+            (IconSet::Unicode, Status::Locking) => "\u{25d4}",
+            (IconSet::Unicode, Status::Connected) => "\u{25cf}",
+            (IconSet::None, _) => return text.to_string(),
+        };
+        format!("<span color='{color}'>{icon}</span> {text}")
+    }
+}
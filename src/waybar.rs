@@ -1,97 +1,748 @@
 use crate::cli::ConnectionOpts;
+use crate::control::{self, OverlayState, SessionState};
 use crate::util::{recv_message, send_message};
-use color_eyre::eyre::Result;
-use serde::Deserialize;
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Context, Result};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::time::Duration;
 use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tracing::warn;
 
-#[derive(Debug, Deserialize)]
-struct StatusMessage {
-    clients: u32,
-    waiting: u32,
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct WaybarOpts {
+    /// The time after which connecting to the server is given up
+    pub connect_timeout_ms: u64,
+    /// The delay before the first reconnect attempt. It doubles on each failed attempt.
+    pub backoff_initial_ms: u64,
+    /// The maximum delay between two reconnect attempts
+    pub backoff_max_ms: u64,
+    /// The text shown in the bar for each state
+    pub text: StateTexts,
+}
+
+impl Default for WaybarOpts {
+    fn default() -> Self {
+        Self {
+            connect_timeout_ms: 5000,
+            backoff_initial_ms: 500,
+            backoff_max_ms: 30000,
+            text: StateTexts::default(),
+        }
+    }
 }
 
-pub async fn main_waybar_status(connection_opts: &ConnectionOpts) -> Result<()> {
-    let status_offline = json!({
-        "text": "Disconnected",
-        "alt": "disconnected",
-        "tooltip": format!("Server: {}\nStatus: Disconnected", connection_opts.address),
-        "class": "disconnected",
-        "clients": 0,
-        "waiting": 0,
-    });
+/// The text of each state, which may contain icons
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct StateTexts {
+    pub disconnected: String,
+    pub connected: String,
+    /// Another client is using the server
+    pub connected_active: String,
+    pub connecting: String,
+    pub locking: String,
+    pub recording: String,
+    pub processing: String,
+    pub error: String,
+}
 
-    let mut last_status = json!({});
-    let mut update_status = |s: serde_json::Value| {
-        if last_status != s {
-            println!("{}", s);
-            last_status = s;
+impl Default for StateTexts {
+    fn default() -> Self {
+        Self {
+            disconnected: "Disconnected".to_string(),
+            connected: "Connected".to_string(),
+            connected_active: "Active".to_string(),
+            connecting: "Connecting".to_string(),
+            locking: "Waiting".to_string(),
+            recording: "Recording".to_string(),
+            processing: "Processing".to_string(),
+            error: "Error".to_string(),
         }
-    };
+    }
+}
+
+impl StateTexts {
+    /// Returns the text for the given `alt` of the status
+    fn get(&self, alt: &str) -> &str {
+        match alt {
+            "connected" => &self.connected,
+            "connected-active" => &self.connected_active,
+            "connecting" => &self.connecting,
+            "locking" => &self.locking,
+            "recording" => &self.recording,
+            "processing" => &self.processing,
+            "error" => &self.error,
+            _ => &self.disconnected,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerModels {
+    #[serde(rename = "final")]
+    pub final_model: String,
+    pub realtime: String,
+}
+
+/// The rolling average latencies of the server in milliseconds
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerLatency {
+    /// The interval between two realtime updates
+    pub realtime_ms: Option<u64>,
+    /// The time from the end of a recording until its final result
+    pub final_ms: Option<u64>,
+}
+
+/// The status sent by the server. All fields except the client counts
+/// are optional, so older servers remain supported.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusMessage {
+    pub clients: u32,
+    pub waiting: u32,
+    #[serde(default)]
+    pub models: Option<ServerModels>,
+    #[serde(default)]
+    pub device: Option<String>,
+    /// The uptime of the server in seconds
+    #[serde(default)]
+    pub uptime: Option<u64>,
+    /// The name of the client which currently holds the model lock
+    #[serde(default)]
+    pub lock_holder: Option<String>,
+    /// The names of the waiting clients, in the order in which they will acquire the lock
+    #[serde(default)]
+    pub queue: Vec<String>,
+    #[serde(default)]
+    pub latency: ServerLatency,
+    /// The interval in seconds in which the server repeats its status, if it does
+    #[serde(default)]
+    pub heartbeat: Option<u64>,
+}
+
+impl StatusMessage {
+    /// Details about the server for the tooltip, one per line
+    fn details(&self) -> Vec<String> {
+        let mut lines = vec![];
+        if let Some(models) = &self.models {
+            lines.push(format!(
+                "Models: {} (realtime: {})",
+                models.final_model, models.realtime
+            ));
+        }
+        if let Some(device) = &self.device {
+            lines.push(format!("Device: {device}"));
+        }
+        if let Some(uptime) = self.uptime {
+            lines.push(format!("Uptime: {}", format_duration(uptime)));
+        }
+        if let Some(holder) = &self.lock_holder {
+            lines.push(format!("Lock holder: {holder}"));
+        }
+        for (i, name) in self.queue.iter().enumerate() {
+            lines.push(format!("Queue #{}: {name}", i + 1));
+        }
+        if let Some(ms) = self.latency.realtime_ms {
+            lines.push(format!("Realtime latency: {ms} ms"));
+        }
+        if let Some(ms) = self.latency.final_ms {
+            lines.push(format!("Final latency: {ms} ms"));
+        }
+        lines
+    }
+}
+
+fn format_duration(seconds: u64) -> String {
+    let (days, hours, minutes) = (seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60);
+    match (days, hours) {
+        (0, 0) => format!("{minutes}m"),
+        (0, _) => format!("{hours}h {minutes}m"),
+        _ => format!("{days}d {hours}h"),
+    }
+}
+
+/// The delay before the next reconnect attempt, which doubles on each failure
+#[derive(Debug, Clone)]
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            current: initial,
+        }
+    }
+
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.current.min(self.max);
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+#[derive(Debug)]
+enum ServerEvent {
+    Status(StatusMessage),
+    Failed,
+}
 
-    'outer: loop {
-        let mut socket = match TcpStream::connect(&connection_opts.address).await {
-            Ok(socket) => socket,
-            Err(_) => {
-                //eprintln!("error: {e}");
-                update_status(status_offline.clone());
-                tokio::time::sleep(Duration::from_secs(5)).await;
-                continue;
+/// Tracks the connection to the server. The status is `None` while the server is unreachable.
+#[derive(Debug)]
+struct ServerMonitor {
+    status: Option<StatusMessage>,
+    backoff: Backoff,
+    connect_timeout: Duration,
+}
+
+impl ServerMonitor {
+    fn new(opts: &WaybarOpts) -> Self {
+        Self {
+            status: None,
+            backoff: Backoff::new(
+                Duration::from_millis(opts.backoff_initial_ms),
+                Duration::from_millis(opts.backoff_max_ms),
+            ),
+            connect_timeout: Duration::from_millis(opts.connect_timeout_ms),
+        }
+    }
+
+    /// Applies an event and returns the delay before reconnecting, if the connection failed.
+    fn handle(&mut self, event: ServerEvent) -> Option<Duration> {
+        match event {
+            ServerEvent::Status(status) => {
+                self.status = Some(status);
+                self.backoff.reset();
+                None
             }
-        };
+            ServerEvent::Failed => {
+                self.status = None;
+                Some(self.backoff.next_delay())
+            }
+        }
+    }
+
+    /// The time after which the connection is considered dead if no message arrives.
+    /// The first message must arrive within the connect timeout, afterwards three
+    /// heartbeats may be missed. Servers without heartbeats are waited for indefinitely.
+    fn receive_timeout(&self) -> Option<Duration> {
+        match &self.status {
+            None => Some(self.connect_timeout),
+            Some(status) => status
+                .heartbeat
+                .map(|heartbeat| Duration::from_secs(heartbeat.max(1) * 3)),
+        }
+    }
+}
+
+/// Connects to the server and requests its status
+async fn connect_status(address: &str, timeout: Duration) -> Result<TcpStream> {
+    let mut socket = tokio::time::timeout(timeout, TcpStream::connect(address))
+        .await
+        .map_err(|_| eyre!("Timed out connecting to {address}"))?
+        .wrap_err_with(|| format!("Could not connect to {address}"))?;
+    send_message(&mut socket, json!({"mode": "status"})).await?;
+    Ok(socket)
+}
 
-        if let Err(e) = send_message(&mut socket, json!({"mode": "status"})).await {
-            eprintln!("error: {e}");
-            update_status(status_offline.clone());
-            tokio::time::sleep(Duration::from_secs(5)).await;
-            continue;
-        }
-
-        loop {
-            let message = match recv_message(&mut socket).await {
-                Ok(value) => value,
-                Err(e) => {
-                    eprintln!("error: {e}");
-                    update_status(status_offline.clone());
-                    tokio::time::sleep(Duration::from_secs(5)).await;
-                    continue 'outer;
+/// Receives the next status, failing if it doesn't arrive within the timeout
+async fn recv_status(socket: &mut TcpStream, timeout: Option<Duration>) -> Result<StatusMessage> {
+    let message = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, recv_message(socket))
+            .await
+            .map_err(|_| eyre!("No status received within {timeout:?}"))??,
+        None => recv_message(socket).await?,
+    };
+    Ok(serde_json::from_value(message)?)
+}
+
+/// Connects to the server and receives its current status
+async fn fetch_status(address: &str, opts: &WaybarOpts) -> Result<StatusMessage> {
+    let timeout = Duration::from_millis(opts.connect_timeout_ms);
+    let mut socket = connect_status(address, timeout).await?;
+    recv_status(&mut socket, Some(timeout)).await
+}
+
+/// Follows the status of the server. `None` is sent while the server is unreachable.
+async fn watch_server(
+    address: String,
+    opts: WaybarOpts,
+    sender: watch::Sender<Option<StatusMessage>>,
+) {
+    let mut monitor = ServerMonitor::new(&opts);
+    loop {
+        let error = match connect_status(&address, monitor.connect_timeout).await {
+            Ok(mut socket) => loop {
+                match recv_status(&mut socket, monitor.receive_timeout()).await {
+                    Ok(status) => {
+                        monitor.handle(ServerEvent::Status(status));
+                        sender.send_replace(monitor.status.clone());
+                    }
+                    Err(e) => break e,
                 }
-            };
-
-            let message: StatusMessage = match serde_json::from_value(message) {
-                Ok(value) => value,
-                Err(e) => {
-                    eprintln!("error: {e}");
-                    update_status(status_offline.clone());
-                    tokio::time::sleep(Duration::from_secs(5)).await;
-                    continue 'outer;
+            },
+            Err(e) => e,
+        };
+
+        // Only report lost connections, not each failed attempt while the server is down
+        if monitor.status.is_some() {
+            warn!("Lost connection to the server: {error:#}");
+        }
+        let delay = monitor.handle(ServerEvent::Failed).unwrap_or_default();
+        sender.send_replace(None);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Follows the state of the overlay running in this session.
+/// `None` is sent while no overlay is running.
+async fn watch_overlay(sender: watch::Sender<Option<OverlayState>>) {
+    loop {
+        // Connecting to the control socket would start a stopped overlay through socket activation
+        let running = tokio::task::spawn_blocking(control::overlay_running)
+            .await
+            .unwrap_or(true);
+        if running {
+            if let Ok(mut socket) = control::subscribe().await {
+                while let Ok(message) = recv_message(&mut socket).await {
+                    match serde_json::from_value(message) {
+                        Ok(state) => sender.send_replace(Some(state)),
+                        Err(e) => {
+                            warn!("Invalid overlay state: {e}");
+                            break;
+                        }
+                    };
                 }
-            };
-
-            let class = format!(
-                "connected{}",
-                (if message.waiting < message.clients {
-                    "-active"
-                } else {
-                    ""
-                })
-            );
-            let status = json!({
-                "text": (if message.waiting < message.clients { "-active" } else { "" }),
-                "alt": class,
-                "tooltip": format!(
-                    "Server: {}\nStatus: Connected\nActive clients: {}\nWaiting clients: {}",
-                    connection_opts.address,
-                    message.clients - message.waiting,
-                    message.waiting
-                ),
-                "class": class,
-                "clients": message.clients,
-                "waiting": message.waiting,
-            });
-
-            update_status(status);
+            }
+        }
+
+        sender.send_replace(None);
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+}
+
+/// The combined status of the server and the local overlay, which is shown in the status bar
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct BarStatus {
+    text: String,
+    /// The name of the status, such as connected or recording
+    alt: String,
+    tooltip: String,
+    class: Vec<String>,
+    clients: u32,
+    waiting: u32,
+    /// The session state of the overlay, if it is running
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state: Option<SessionState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+}
+
+impl BarStatus {
+    /// A color which represents the status, for bars without css styling
+    fn color(&self) -> &'static str {
+        match self.alt.as_str() {
+            "connected" => "#4ab0fa",
+            "connected-active" | "recording" | "error" => "#ff5050",
+            "connecting" | "locking" | "processing" => "#ffa500",
+            _ => "#888888",
         }
     }
 }
+
+/// Renders the status for a specific status bar
+pub trait StatusFormatter {
+    /// Printed once before the first status
+    fn header(&self) -> Option<String> {
+        None
+    }
+
+    /// Renders a status, which is printed on a single line
+    fn format(&self, status: &BarStatus) -> Result<String>;
+}
+
+/// The json format of waybar's custom modules
+struct Waybar;
+
+impl StatusFormatter for Waybar {
+    fn format(&self, status: &BarStatus) -> Result<String> {
+        let mut value = json!({
+            "text": status.text,
+            "alt": status.alt,
+            "tooltip": status.tooltip,
+            "class": status.class,
+            "clients": status.clients,
+            "waiting": status.waiting,
+        });
+        if let Some(state) = status.state {
+            value["state"] = json!(state);
+            value["profile"] = json!(status.profile);
+            value["language"] = json!(status.language);
+        }
+        Ok(value.to_string())
+    }
+}
+
+/// The i3bar protocol, which is also used by swaybar
+struct I3bar;
+
+impl StatusFormatter for I3bar {
+    fn header(&self) -> Option<String> {
+        Some(format!("{}\n[", json!({"version": 1})))
+    }
+
+    fn format(&self, status: &BarStatus) -> Result<String> {
+        let block = json!({
+            "name": "whisper-overlay",
+            "full_text": status.text,
+            "short_text": status.alt,
+            "color": status.color(),
+        });
+        Ok(format!("[{block}],"))
+    }
+}
+
+/// A plain line of text, as used by polybar's script module
+struct Plain;
+
+impl StatusFormatter for Plain {
+    fn format(&self, status: &BarStatus) -> Result<String> {
+        Ok(match &status.language {
+            Some(language) => format!("{} [{language}]", status.text),
+            None => status.text.clone(),
+        })
+    }
+}
+
+/// The tag based format of yambar's script module
+struct Yambar;
+
+impl StatusFormatter for Yambar {
+    fn format(&self, status: &BarStatus) -> Result<String> {
+        let state = status.state.map_or("none", |x| x.as_str());
+        Ok(format!(
+            "status|string|{}\nstate|string|{state}\nlanguage|string|{}\nprofile|string|{}\nclients|int|{}\nwaiting|int|{}\n",
+            status.alt,
+            status.language.as_deref().unwrap_or_default(),
+            status.profile.as_deref().unwrap_or_default(),
+            status.clients,
+            status.waiting,
+        ))
+    }
+}
+
+/// The whole status as a json object, for widgets of eww or ags
+struct Json;
+
+impl StatusFormatter for Json {
+    fn format(&self, status: &BarStatus) -> Result<String> {
+        Ok(serde_json::to_string(status)?)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StatusFormat {
+    /// Json for waybar's custom modules
+    #[default]
+    Waybar,
+    /// The i3bar protocol, also used by swaybar
+    I3bar,
+    /// Plain text, for example for polybar
+    Plain,
+    /// Tagged values for yambar's script module
+    Yambar,
+    /// The full status as a json object, for example for eww or ags
+    Json,
+}
+
+impl StatusFormat {
+    fn formatter(self) -> Box<dyn StatusFormatter> {
+        match self {
+            StatusFormat::Waybar => Box::new(Waybar),
+            StatusFormat::I3bar => Box::new(I3bar),
+            StatusFormat::Plain => Box::new(Plain),
+            StatusFormat::Yambar => Box::new(Yambar),
+            StatusFormat::Json => Box::new(Json),
+        }
+    }
+}
+
+fn server_status(address: &str, message: Option<&StatusMessage>) -> BarStatus {
+    let Some(message) = message else {
+        return BarStatus {
+            alt: "disconnected".to_string(),
+            tooltip: format!("Server: {}\nStatus: Disconnected", address),
+            class: vec!["disconnected".to_string()],
+            ..BarStatus::default()
+        };
+    };
+
+    // The server may briefly report more waiting clients than clients while they disconnect
+    let active = message.clients.saturating_sub(message.waiting);
+    let alt = if active > 0 {
+        "connected-active"
+    } else {
+        "connected"
+    };
+    BarStatus {
+        alt: alt.to_string(),
+        tooltip: [format!(
+            "Server: {}\nStatus: Connected\nActive clients: {}\nWaiting clients: {}",
+            address, active, message.waiting
+        )]
+        .into_iter()
+        .chain(message.details())
+        .collect::<Vec<_>>()
+        .join("\n"),
+        class: vec![alt.to_string()],
+        clients: message.clients,
+        waiting: message.waiting,
+        ..BarStatus::default()
+    }
+}
+
+/// Merges the server status with the state of the local overlay. While the overlay
+/// is in a session, its state replaces the `alt` of the server status.
+fn merged_status(
+    address: &str,
+    message: Option<&StatusMessage>,
+    overlay: Option<&OverlayState>,
+    texts: &StateTexts,
+) -> BarStatus {
+    let mut status = server_status(address, message);
+    if let Some(overlay) = overlay {
+        merge_overlay(&mut status, overlay);
+    }
+    status.text = texts.get(&status.alt).to_string();
+    status
+}
+
+fn merge_overlay(status: &mut BarStatus, overlay: &OverlayState) {
+    let state = overlay.state.as_str();
+    if overlay.state != SessionState::Idle {
+        status.alt = state.to_string();
+    }
+    status.class.push(format!("overlay-{state}"));
+
+    let language = match (&overlay.language, &overlay.detected_language) {
+        (Some(language), _) => language.clone(),
+        (None, Some(detected)) => format!("auto ({detected})"),
+        (None, None) => "auto".to_string(),
+    };
+    let mut tooltip = format!(
+        "{}\nOverlay: {state}\nProfile: {}\nLanguage: {language}",
+        status.tooltip, overlay.profile,
+    );
+    if overlay.translate {
+        tooltip += "\nTranslating to english";
+    }
+    if let Some(position) = overlay.queue_position {
+        tooltip += &format!("\nQueue position: {position}");
+    }
+    if overlay.review_pending {
+        tooltip += "\nText pending review";
+    }
+    if let Some(error) = &overlay.error {
+        tooltip += &format!("\nError: {error}");
+    }
+    status.tooltip = tooltip;
+    status.state = Some(overlay.state);
+    status.profile = Some(overlay.profile.clone());
+    status.language = Some(language);
+}
+
+pub async fn main_waybar_status(
+    connection_opts: &ConnectionOpts,
+    opts: WaybarOpts,
+    format: StatusFormat,
+) -> Result<()> {
+    let formatter = format.formatter();
+    if let Some(header) = formatter.header() {
+        println!("{header}");
+    }
+
+    let (server_sender, mut server_receiver) = watch::channel(None);
+    let (overlay_sender, mut overlay_receiver) = watch::channel(None);
+    let texts = opts.text.clone();
+    tokio::spawn(watch_server(
+        connection_opts.address.clone(),
+        opts,
+        server_sender,
+    ));
+    tokio::spawn(watch_overlay(overlay_sender));
+
+    let mut last_status = None;
+    loop {
+        let status = merged_status(
+            &connection_opts.address,
+            server_receiver.borrow_and_update().as_ref(),
+            overlay_receiver.borrow_and_update().as_ref(),
+            &texts,
+        );
+        if last_status.as_ref() != Some(&status) {
+            println!("{}", formatter.format(&status)?);
+            last_status = Some(status);
+        }
+
+        tokio::select! {
+            result = server_receiver.changed() => result?,
+            result = overlay_receiver.changed() => result?,
+        }
+    }
+}
+
+/// Prints the current status of the server once
+pub async fn main_server_status(
+    connection_opts: &ConnectionOpts,
+    opts: &WaybarOpts,
+    json: bool,
+) -> Result<()> {
+    let status = fetch_status(&connection_opts.address, opts).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+
+    println!("Server: {}", connection_opts.address);
+    println!("Clients: {} ({} waiting)", status.clients, status.waiting);
+    for line in status.details() {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(clients: u32, waiting: u32) -> StatusMessage {
+        StatusMessage {
+            clients,
+            waiting,
+            ..StatusMessage::default()
+        }
+    }
+
+    fn opts() -> WaybarOpts {
+        WaybarOpts {
+            backoff_initial_ms: 100,
+            backoff_max_ms: 1000,
+            ..WaybarOpts::default()
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_and_resets() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(500));
+        let delays: Vec<_> = (0..5).map(|_| backoff.next_delay().as_millis()).collect();
+        assert_eq!(delays, [100, 200, 400, 500, 500]);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn monitor_resets_backoff_on_status() {
+        let mut monitor = ServerMonitor::new(&opts());
+        assert_eq!(
+            monitor.handle(ServerEvent::Failed),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(
+            monitor.handle(ServerEvent::Failed),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(monitor.status, None);
+
+        assert_eq!(monitor.handle(ServerEvent::Status(message(1, 0))), None);
+        assert_eq!(monitor.status, Some(message(1, 0)));
+        assert_eq!(
+            monitor.handle(ServerEvent::Failed),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(monitor.status, None);
+    }
+
+    #[test]
+    fn receive_timeout_follows_heartbeat() {
+        let mut monitor = ServerMonitor::new(&opts());
+        // The first status must arrive within the connect timeout
+        assert_eq!(monitor.receive_timeout(), Some(Duration::from_millis(5000)));
+
+        // Servers without heartbeats only send changes
+        monitor.handle(ServerEvent::Status(message(0, 0)));
+        assert_eq!(monitor.receive_timeout(), None);
+
+        monitor.handle(ServerEvent::Status(StatusMessage {
+            heartbeat: Some(10),
+            ..message(0, 0)
+        }));
+        assert_eq!(monitor.receive_timeout(), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn more_waiting_than_clients_does_not_underflow() {
+        let status = server_status("host:7007", Some(&message(1, 3)));
+        assert_eq!(status.alt, "connected");
+        assert!(status.tooltip.contains("Active clients: 0"));
+    }
+
+    #[test]
+    fn text_matches_state() {
+        let texts = StateTexts::default();
+        let text = |message: Option<&StatusMessage>| {
+            merged_status("host:7007", message, None, &texts).text
+        };
+        assert_eq!(text(None), "Disconnected");
+        assert_eq!(text(Some(&message(0, 0))), "Connected");
+        assert_eq!(text(Some(&message(2, 1))), "Active");
+    }
+
+    #[test]
+    fn custom_texts_are_used() {
+        let texts = StateTexts {
+            connected_active: "busy".to_string(),
+            ..StateTexts::default()
+        };
+        let status = merged_status("host:7007", Some(&message(1, 0)), None, &texts);
+        assert_eq!(status.alt, "connected-active");
+        assert_eq!(status.text, "busy");
+        assert_eq!(status.class, ["connected-active"]);
+    }
+
+    #[test]
+    fn overlay_state_replaces_server_state() {
+        let overlay = OverlayState {
+            state: SessionState::Recording,
+            profile: "default".to_string(),
+            ..OverlayState::default()
+        };
+        let status = merged_status(
+            "host:7007",
+            Some(&message(1, 0)),
+            Some(&overlay),
+            &StateTexts::default(),
+        );
+        assert_eq!(status.alt, "recording");
+        assert_eq!(status.text, "Recording");
+        assert_eq!(status.class, ["connected-active", "overlay-recording"]);
+
+        // An idle overlay keeps the server state
+        let overlay = OverlayState::default();
+        let status = merged_status("host:7007", None, Some(&overlay), &StateTexts::default());
+        assert_eq!(status.alt, "disconnected");
+        assert_eq!(status.class, ["disconnected", "overlay-idle"]);
+    }
+}
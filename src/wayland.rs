@@ -0,0 +1,283 @@
+use color_eyre::eyre::{bail, Result};
+use std::io::Write;
+use std::os::fd::AsFd;
+use std::time::{Duration, Instant};
+use wayland_client::globals::{registry_queue_init, GlobalList, GlobalListContents};
+use wayland_client::protocol::wl_keyboard::KeymapFormat;
+use wayland_client::protocol::wl_registry::{self, WlRegistry};
+use wayland_client::protocol::wl_seat::WlSeat;
+use wayland_client::{delegate_noop, Connection, Dispatch, EventQueue, QueueHandle};
+use wayland_protocols_misc::zwp_input_method_v2::client::zwp_input_method_manager_v2::ZwpInputMethodManagerV2;
+use wayland_protocols_misc::zwp_input_method_v2::client::zwp_input_method_v2::{
+    self, ZwpInputMethodV2,
+};
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1;
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1;
+
+use crate::keyboard::BackendError;
+
+/// The number of characters typed per keymap. The keymap can hold at most
+/// 247 keys, since xkb keycodes start at 8 and are limited to 255.
+const KEYMAP_CHUNK_SIZE: usize = 200;
+
+/// The modifier mask of the control key
+const MODIFIER_CONTROL: u32 = 1 << 2;
+
+#[derive(Debug, Default)]
+struct State {
+    input_method_active: bool,
+    input_method_pending_active: bool,
+    input_method_unavailable: bool,
+    /// The number of done events received, which must be passed on commit
+    input_method_serial: u32,
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwpInputMethodV2, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &ZwpInputMethodV2,
+        event: zwp_input_method_v2::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwp_input_method_v2::Event::Activate => state.input_method_pending_active = true,
+            zwp_input_method_v2::Event::Deactivate => state.input_method_pending_active = false,
+            zwp_input_method_v2::Event::Done => {
+                state.input_method_active = state.input_method_pending_active;
+                state.input_method_serial += 1;
+            }
+            zwp_input_method_v2::Event::Unavailable => state.input_method_unavailable = true,
+            _ => {}
+        }
+    }
+}
+
+delegate_noop!(State: ignore WlSeat);
+delegate_noop!(State: ZwpVirtualKeyboardManagerV1);
+delegate_noop!(State: ZwpVirtualKeyboardV1);
+delegate_noop!(State: ZwpInputMethodManagerV2);
+
+fn keysym_name(c: char) -> String {
+    match c {
+        '\n' => "Return".to_string(),
+        '\t' => "Tab".to_string(),
+        '\u{8}' => "BackSpace".to_string(),
+        c => format!("U{:04X}", c as u32),
+    }
+}
+
+/// Builds a keymap which maps each of the given characters to its own key,
+/// starting at xkb keycode 9 (evdev keycode 1).
+fn build_keymap(symbols: &[char]) -> String {
+    let mut keycodes = String::new();
+    let mut keysyms = String::new();
+    for (i, c) in symbols.iter().enumerate() {
+        let keycode = i + 9;
+        keycodes += &format!("<K{keycode}> = {keycode};\n");
+        keysyms += &format!("key <K{keycode}> {{ [ {} ] }};\n", keysym_name(*c));
+    }
+
+    format!(
+        "xkb_keymap {{\n\
+         xkb_keycodes \"(unnamed)\" {{\nminimum = 8;\nmaximum = {maximum};\n{keycodes}}};\n\
+         xkb_types \"(unnamed)\" {{ include \"complete\" }};\n\
+         xkb_compatibility \"(unnamed)\" {{ include \"complete\" }};\n\
+         xkb_symbols \"(unnamed)\" {{\n{keysyms}}};\n\
+         }};\n",
+        maximum = symbols.len() + 9,
+    )
+}
+
+/// A connection to the wayland compositor which can type arbitrary text
+/// using the virtual-keyboard-v1 or input-method-v2 protocols.
+pub struct WaylandTyper {
+    connection: Connection,
+    queue: EventQueue<State>,
+    qh: QueueHandle<State>,
+    globals: GlobalList,
+    state: State,
+    seat: WlSeat,
+    virtual_keyboard: Option<ZwpVirtualKeyboardV1>,
+    start: Instant,
+}
+
+impl WaylandTyper {
+    pub fn connect() -> Result<Self> {
+        let connection = Connection::connect_to_env()?;
+        let (globals, mut queue) = registry_queue_init::<State>(&connection)?;
+        let qh = queue.handle();
+        let seat: WlSeat = globals.bind(&qh, 1..=1, ())?;
+
+        let mut state = State::default();
+        queue.roundtrip(&mut state)?;
+
+        Ok(Self {
+            connection,
+            queue,
+            qh,
+            globals,
+            state,
+            seat,
+            virtual_keyboard: None,
+            start: Instant::now(),
+        })
+    }
+
+    fn virtual_keyboard(&mut self) -> Result<ZwpVirtualKeyboardV1> {
+        if let Some(virtual_keyboard) = &self.virtual_keyboard {
+            return Ok(virtual_keyboard.clone());
+        }
+
+        let manager: ZwpVirtualKeyboardManagerV1 = self.globals.bind(&self.qh, 1..=1, ())?;
+        let virtual_keyboard = manager.create_virtual_keyboard(&self.seat, &self.qh, ());
+        self.virtual_keyboard = Some(virtual_keyboard.clone());
+        Ok(virtual_keyboard)
+    }
+
+    /// Types the given text by pressing keys on a virtual keyboard. Since the
+    /// keymap is generated for the text, this supports any unicode character.
+    pub fn type_virtual_keyboard(
+        &mut self,
+        text: &str,
+        key_delay: Duration,
+    ) -> Result<(), BackendError> {
+        self.press_keys(text, 0, key_delay)
+    }
+
+    /// Presses Ctrl+V on the virtual keyboard.
+    pub fn paste(&mut self) -> Result<(), BackendError> {
+        self.press_keys("v", MODIFIER_CONTROL, Duration::ZERO)
+    }
+
+    fn press_keys(
+        &mut self,
+        text: &str,
+        modifiers: u32,
+        key_delay: Duration,
+    ) -> Result<(), BackendError> {
+        let mut pressed = false;
+        self.press_chunks(text, modifiers, key_delay, &mut pressed)
+            .map_err(|e| BackendError::new(e, pressed))
+    }
+
+    fn press_chunks(
+        &mut self,
+        text: &str,
+        modifiers: u32,
+        key_delay: Duration,
+        pressed: &mut bool,
+    ) -> Result<()> {
+        let virtual_keyboard = self.virtual_keyboard()?;
+        let chars: Vec<char> = text.chars().collect();
+
+        for chunk in chars.chunks(KEYMAP_CHUNK_SIZE) {
+            let mut symbols: Vec<char> = vec![];
+            for c in chunk {
+                if !symbols.contains(c) {
+                    symbols.push(*c);
+                }
+            }
+
+            // The keymap is passed to the compositor as a file descriptor,
+            // which must stay open until the compositor has read it.
+            let keymap = build_keymap(&symbols);
+            let mut file = tempfile::tempfile()?;
+            file.write_all(keymap.as_bytes())?;
+            file.write_all(&[0])?;
+            file.flush()?;
+            virtual_keyboard.keymap(
+                KeymapFormat::XkbV1 as u32,
+                file.as_fd(),
+                keymap.len() as u32 + 1,
+            );
+            virtual_keyboard.modifiers(modifiers, 0, 0, 0);
+            self.queue.roundtrip(&mut self.state)?;
+
+            for c in chunk {
+                let Some(index) = symbols.iter().position(|x| x == c) else {
+                    continue;
+                };
+
+                // Evdev keycodes are offset by 8 from xkb keycodes
+                let keycode = index as u32 + 1;
+                let time = self.start.elapsed().as_millis() as u32;
+                virtual_keyboard.key(time, keycode, 1);
+                virtual_keyboard.key(time, keycode, 0);
+                self.connection.flush()?;
+                *pressed = true;
+                if !key_delay.is_zero() {
+                    std::thread::sleep(key_delay);
+                }
+            }
+
+            if modifiers != 0 {
+                virtual_keyboard.modifiers(0, 0, 0, 0);
+            }
+
+            self.queue.roundtrip(&mut self.state)?;
+        }
+
+        Ok(())
+    }
+
+    /// Commits the given text directly into the focused text field. This only
+    /// works if the focused application supports text-input-v3 and no other
+    /// input method is running.
+    ///
+    /// The input method is only registered for the duration of the commit, since
+    /// a seat can only have one and it would otherwise block fcitx or ibus.
+    pub fn commit_input_method(&mut self, text: &str) -> Result<(), BackendError> {
+        let manager: ZwpInputMethodManagerV2 = self
+            .globals
+            .bind(&self.qh, 1..=1, ())
+            .map_err(|e| BackendError::new(e, false))?;
+        let input_method = manager.get_input_method(&self.seat, &self.qh, ());
+        self.state.input_method_active = false;
+        self.state.input_method_pending_active = false;
+        self.state.input_method_unavailable = false;
+        self.state.input_method_serial = 0;
+
+        let mut committed = false;
+        let result = self.commit_string(&input_method, text, &mut committed);
+        input_method.destroy();
+        manager.destroy();
+        let result = result.and_then(|()| Ok(self.queue.roundtrip(&mut self.state)?));
+        result.map_err(|e| BackendError::new(e, committed))
+    }
+
+    fn commit_string(
+        &mut self,
+        input_method: &ZwpInputMethodV2,
+        text: &str,
+        committed: &mut bool,
+    ) -> Result<()> {
+        self.queue.roundtrip(&mut self.state)?;
+
+        if self.state.input_method_unavailable {
+            bail!("input method is unavailable, is another input method running?");
+        }
+        if !self.state.input_method_active {
+            bail!("the focused application does not accept text input");
+        }
+
+        input_method.commit_string(text.to_string());
+        input_method.commit(self.state.input_method_serial);
+        *committed = true;
+        self.queue.roundtrip(&mut self.state)?;
+        Ok(())
+    }
+}